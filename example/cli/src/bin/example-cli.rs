@@ -1,4 +1,4 @@
-use std::{env, process::exit, time::Duration};
+use std::{env, process::exit};
 
 use clap::{Parser, Subcommand};
 use sqlx::migrate;
@@ -15,27 +15,9 @@ enum Commands {
     InstallSchema,
 }
 
-async fn create_simple_connection(db_url: &str) -> Result<sqlx::PgConnection, sqlx::Error> {
-    use sqlx::Connection;
-    let mut attempts = 0;
-    loop {
-        match sqlx::PgConnection::connect(db_url).await {
-            Ok(connection) => return Ok(connection),
-            Err(e) => {
-                attempts += 1;
-                if attempts > 20 {
-                    println!("max connection failed");
-                    return Err(e);
-                } else {
-                    tokio::time::sleep(Duration::from_millis(500)).await;
-                }
-            }
-        }   
-    }   
-}
-
 async fn install_schema(db_url: &str) {
-    let mut pool = create_simple_connection(db_url).await.unwrap();
+    use sqlx::Connection;
+    let mut pool = sqlx::PgConnection::connect(db_url).await.unwrap();
     migrate!("db/migrations").run(&mut pool).await.unwrap();
 }
 
@@ -1,21 +1,44 @@
-use itest_runner::itest;
+use itest_runner::{TestContext, depends_on, itest};
 use reqwest::StatusCode;
 
 #[itest]
-fn can_not_call_server_directly_with_http1() {
-    let response = reqwest::blocking::get("http://localhost:3000/").unwrap();
+#[depends_on(Server)]
+fn can_not_call_server_directly_with_http1(ctx: TestContext) -> Result<(), reqwest::Error> {
+    let url = ctx.get_param("Server.url").unwrap();
+    let response = reqwest::blocking::get(url.as_str())?;
     assert_eq!(StatusCode::HTTP_VERSION_NOT_SUPPORTED, response.status());
-    let body = response.text().unwrap();
+    let body = response.text()?;
     assert_eq!(
         r#"{"error":"This server only accepts HTTP/2 connections","received_version":"HTTP/1.1"}"#,
         body
     );
+    Ok(())
 }
 
 #[itest]
-fn can_call_server_via_envoy_with_http1() {
-    let response = reqwest::blocking::get("http://localhost:8080/").unwrap();
+#[depends_on(Envoy)]
+async fn can_call_server_via_envoy_with_http1(ctx: TestContext) {
+    let url = ctx.get_param("Envoy.url").unwrap();
+    let response = reqwest::get(url.as_str()).await.unwrap();
     assert_eq!(StatusCode::OK, response.status());
-    let body = response.text().unwrap();
+    let body = response.text().await.unwrap();
     assert_eq!(r#"{"message":"Hello, World!"}"#, body);
 }
+
+#[itest(
+    case(name = "direct", args("Server.url")),
+    case(name = "via_envoy", args("Envoy.url"))
+)]
+#[depends_on(Envoy)]
+async fn can_call_server_with_http2(
+    ctx: TestContext,
+    url_param: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let url = ctx.get_param(url_param).unwrap();
+    let client = reqwest::Client::builder().http2_prior_knowledge().build()?;
+    let response = client.get(url.as_str()).send().await?;
+    assert_eq!(StatusCode::OK, response.status());
+    let body = response.text().await?;
+    assert_eq!(r#"{"message":"Hello, World!"}"#, body);
+    Ok(())
+}
@@ -1,6 +1,7 @@
 use itest_runner::ITest;
 
 mod basic_connectivity;
+mod resources;
 mod set_up;
 
 fn main() {
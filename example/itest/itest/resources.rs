@@ -0,0 +1,25 @@
+use std::{any::type_name, sync::Arc};
+
+use itest_runner::{ResourceError, TestContext, depends_on, itest};
+
+use crate::set_up::postgres::PostgresDb;
+
+#[itest]
+#[depends_on(Postgres)]
+fn tests_get_the_resource_of_a_set_up(ctx: TestContext, #[resource(Postgres)] db: Arc<PostgresDb>) {
+    assert_eq!(ctx.get_param("Postgres.url").unwrap().as_str(), db.url);
+}
+
+#[itest]
+#[depends_on(Postgres)]
+fn resources_of_another_type_are_rejected(ctx: TestContext) {
+    let err = ctx.resource::<String>("Postgres").unwrap_err();
+    assert_eq!(
+        ResourceError::TypeMismatch {
+            component: "Postgres".to_owned(),
+            requested: type_name::<String>(),
+            provided: type_name::<PostgresDb>(),
+        },
+        err
+    );
+}
@@ -1,9 +1,11 @@
 use std::path::Path;
 
 use itest_runner::{
-    Context, TearDown, components::container::ContainerTearDown, depends_on, set_up,
+    Context, TearDown,
+    components::{container::ContainerSetUp, wait::Probe},
+    depends_on, set_up,
 };
-use testcontainers::{GenericImage, ImageExt, core::Mount, runners::AsyncRunner};
+use testcontainers::{GenericImage, ImageExt, core::Mount};
 
 #[set_up(Envoy)]
 #[depends_on(Server)]
@@ -19,9 +21,10 @@ async fn set_up(ctx: Context) -> Result<impl TearDown, Box<dyn std::error::Error
         )
         .with_network("host");
 
-    let container = image.start().await?;
-    ctx.monitor_async("stdout", container.stdout(true));
-    ctx.monitor_async("stderr", container.stderr(true));
+    ctx.set_param("url", "http://localhost:8080/");
 
-    Ok(ContainerTearDown::new(container))
+    ContainerSetUp::new(image)
+        .wait_for(Probe::tcp("localhost:8080"))
+        .start(&ctx)
+        .await
 }
@@ -1,7 +1,7 @@
 use itest_runner::set_up;
 
 mod envoy;
-mod postgres;
+pub(crate) mod postgres;
 mod redis;
 mod server;
 
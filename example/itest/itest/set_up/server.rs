@@ -1,25 +1,31 @@
 use itest_runner::{
     Context, TearDown,
-    components::{localcli::LocalCliSetUp, localserver::LocalServerSetUp},
+    components::{localcli::LocalCliSetUp, localserver::LocalServerSetUp, wait::Probe},
     depends_on, set_up,
 };
 
-#[set_up(Schema)]
+use super::postgres::PostgresDb;
+
+// postgres may still be starting when the container is up
+#[set_up(Schema, retries = 5, backoff = "exponential", retry_delay = "500ms")]
 #[depends_on(Postgres)]
 fn install_schema(ctx: Context) -> Result<(), Box<dyn std::error::Error>> {
-    let db_url = ctx.get_param("Postgres.url").unwrap();
+    let db = ctx.resource::<PostgresDb>("Postgres")?;
     LocalCliSetUp::new("example-cli")
         .with_args(&["install-schema"])
-        .with_envs(&[("EXAMPLE_DATABASE_URL", db_url.as_str())])
+        .with_envs(&[("EXAMPLE_DATABASE_URL", db.url.as_str())])
         .run(ctx)
 }
 
 #[set_up(Server)]
 #[depends_on(Schema)]
 #[depends_on(Redis)]
-fn start_server(ctx: Context) -> Result<impl TearDown, Box<dyn std::error::Error>> {
-    let db_url = ctx.get_param("Postgres.url").unwrap();
+async fn start_server(ctx: Context) -> Result<impl TearDown, Box<dyn std::error::Error>> {
+    let db = ctx.resource::<PostgresDb>("Postgres")?;
+    ctx.set_param("url", "http://localhost:3000/");
     LocalServerSetUp::new("example-server")
-        .with_envs(&[("EXAMPLE_DATABASE_URL", db_url.as_str())])
+        .with_envs(&[("EXAMPLE_DATABASE_URL", db.url.as_str())])
+        .wait_for(Probe::tcp("localhost:3000"))
         .start(ctx)
+        .await
 }
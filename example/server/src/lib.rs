@@ -64,8 +64,7 @@ async fn force_http2_only(request: Request, next: Next) -> Response {
 
 async fn connect_to_database(db_url: &str) -> Pool<Postgres> {
     println!("Connecting to database");
-    let pool = Pool::<Postgres>::connect(db_url).await.unwrap();
-    pool
+    Pool::<Postgres>::connect(db_url).await.unwrap()
 }
 
 #[derive(Clone)]
@@ -1,34 +1,674 @@
 use proc_macro::TokenStream;
 use quote::quote;
-use syn::{Error, Ident, ReturnType, spanned::Spanned};
+use syn::{Error, Ident, LitStr, ReturnType, Token, punctuated::Punctuated, spanned::Spanned};
+
+/// Options accepted by `#[itest(...)]`
+#[derive(Default)]
+struct ITestArgs {
+    ignore: Option<String>,
+    should_panic: Option<Option<String>>,
+    timeout_ms: Option<u64>,
+    cases: Vec<TestCase>,
+}
+
+/// A `case(name = "...", args(...))` entry, registered as its own test
+struct TestCase {
+    name: LitStr,
+    args: Vec<syn::Expr>,
+}
+
+impl TestCase {
+    fn parse_meta(meta: syn::meta::ParseNestedMeta) -> syn::Result<TestCase> {
+        let mut name = None;
+        let mut args = Vec::new();
+        meta.parse_nested_meta(|nested| {
+            if nested.path.is_ident("name") {
+                name = Some(nested.value()?.parse::<LitStr>()?);
+                Ok(())
+            } else if nested.path.is_ident("args") {
+                let content;
+                syn::parenthesized!(content in nested.input);
+                let exprs = Punctuated::<syn::Expr, Token![,]>::parse_terminated(&content)?;
+                args = exprs.into_iter().collect();
+                Ok(())
+            } else {
+                Err(nested.error("unsupported case property"))
+            }
+        })?;
+
+        match name {
+            Some(name) => Ok(TestCase { name, args }),
+            None => Err(meta.error("case requires a name")),
+        }
+    }
+
+    fn ident_suffix(&self) -> String {
+        self.name
+            .value()
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect()
+    }
+}
+
+impl ITestArgs {
+    fn parse_meta(&mut self, meta: syn::meta::ParseNestedMeta) -> syn::Result<()> {
+        if meta.path.is_ident("ignore") {
+            let reason = if meta.input.peek(Token![=]) {
+                meta.value()?.parse::<LitStr>()?.value()
+            } else {
+                String::new()
+            };
+            self.ignore = Some(reason);
+            Ok(())
+        } else if meta.path.is_ident("should_panic") {
+            let mut expected = None;
+            if meta.input.peek(syn::token::Paren) {
+                meta.parse_nested_meta(|nested| {
+                    if nested.path.is_ident("expected") {
+                        expected = Some(nested.value()?.parse::<LitStr>()?.value());
+                        Ok(())
+                    } else {
+                        Err(nested.error("unsupported should_panic property"))
+                    }
+                })?;
+            }
+            self.should_panic = Some(expected);
+            Ok(())
+        } else if meta.path.is_ident("timeout") {
+            let timeout = meta.value()?.parse::<LitStr>()?;
+            self.timeout_ms = Some(parse_duration_ms(&timeout)?);
+            Ok(())
+        } else if meta.path.is_ident("case") {
+            let case = TestCase::parse_meta(meta)?;
+            if self.cases.iter().any(|c| c.name.value() == case.name.value()) {
+                return Err(Error::new(case.name.span(), "Duplicate case name"));
+            }
+            // names only differing in punctuation get the same wrapper function
+            if let Some(other) = self.cases.iter().find(|c| c.ident_suffix() == case.ident_suffix()) {
+                return Err(Error::new(
+                    case.name.span(),
+                    format!(
+                        "Case name {:?} clashes with {:?}, use names that differ in letters or digits",
+                        case.name.value(),
+                        other.name.value()
+                    ),
+                ));
+            }
+            self.cases.push(case);
+            Ok(())
+        } else {
+            Err(meta.error("unsupported itest property"))
+        }
+    }
+}
+
+/// Parse durations such as "500ms", "30s", "5m" or "1h" into milliseconds
+fn parse_duration_ms(lit: &LitStr) -> syn::Result<u64> {
+    let value = lit.value();
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+
+    let scale = match unit.trim() {
+        "ms" => 1,
+        "s" => 1_000,
+        "m" => 60_000,
+        "h" => 3_600_000,
+        _ => {
+            return Err(Error::new(
+                lit.span(),
+                "Expected a duration such as \"500ms\", \"30s\", \"5m\" or \"1h\"",
+            ));
+        }
+    };
+
+    amount
+        .parse::<u64>()
+        .map_err(|_| Error::new(lit.span(), "Expected a whole number before the duration unit"))?
+        .checked_mul(scale)
+        .ok_or_else(|| Error::new(lit.span(), "Duration is too large"))
+}
+
+fn optional_duration(ms: Option<u64>) -> proc_macro2::TokenStream {
+    match ms {
+        Some(ms) => quote! { Some(::std::time::Duration::from_millis(#ms)) },
+        None => quote! { None },
+    }
+}
+
+/// Arguments of `#[set_up(Name, ...)]`
+struct SetUpArgs {
+    name: Ident,
+    cpu: u32,
+    mem_bytes: u64,
+    timeout_ms: Option<u64>,
+    retries: u32,
+    backoff: Option<LitStr>,
+    retry_delay_ms: u64,
+}
+
+impl syn::parse::Parse for SetUpArgs {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let mut args = SetUpArgs {
+            name: input.parse()?,
+            cpu: 0,
+            mem_bytes: 0,
+            timeout_ms: None,
+            retries: 0,
+            backoff: None,
+            retry_delay_ms: 1_000,
+        };
+        if input.parse::<Option<Token![,]>>()?.is_some() {
+            let options: proc_macro2::TokenStream = input.parse()?;
+            let options_parser = syn::meta::parser(|meta| args.parse_meta(meta));
+            syn::parse::Parser::parse2(options_parser, options)?;
+        }
+        Ok(args)
+    }
+}
+
+impl SetUpArgs {
+    fn parse_meta(&mut self, meta: syn::meta::ParseNestedMeta) -> syn::Result<()> {
+        if meta.path.is_ident("cost") {
+            meta.parse_nested_meta(|nested| {
+                if nested.path.is_ident("cpu") {
+                    self.cpu = nested.value()?.parse::<syn::LitInt>()?.base10_parse()?;
+                    Ok(())
+                } else if nested.path.is_ident("mem") {
+                    self.mem_bytes = parse_mem_bytes(&nested.value()?.parse::<LitStr>()?)?;
+                    Ok(())
+                } else {
+                    Err(nested.error("unsupported cost property, expected cpu or mem"))
+                }
+            })
+        } else if meta.path.is_ident("timeout") {
+            let timeout = meta.value()?.parse::<LitStr>()?;
+            self.timeout_ms = Some(parse_duration_ms(&timeout)?);
+            Ok(())
+        } else if meta.path.is_ident("retries") {
+            self.retries = meta.value()?.parse::<syn::LitInt>()?.base10_parse()?;
+            Ok(())
+        } else if meta.path.is_ident("backoff") {
+            let backoff = meta.value()?.parse::<LitStr>()?;
+            if !matches!(backoff.value().as_str(), "fixed" | "exponential") {
+                return Err(Error::new(backoff.span(), "Expected \"fixed\" or \"exponential\""));
+            }
+            self.backoff = Some(backoff);
+            Ok(())
+        } else if meta.path.is_ident("retry_delay") {
+            let delay = meta.value()?.parse::<LitStr>()?;
+            self.retry_delay_ms = parse_duration_ms(&delay)?;
+            Ok(())
+        } else {
+            Err(meta.error("unsupported set_up property"))
+        }
+    }
+
+    fn retry(&self) -> proc_macro2::TokenStream {
+        let retries = self.retries;
+        let delay = self.retry_delay_ms;
+        let backoff = match self.backoff.as_ref().map(|b| b.value()).as_deref() {
+            Some("exponential") => quote! { Exponential },
+            _ => quote! { Fixed },
+        };
+        quote! {
+            ::itest_runner::Retry {
+                retries: #retries,
+                backoff: ::itest_runner::Backoff::#backoff(::std::time::Duration::from_millis(#delay)),
+            }
+        }
+    }
+}
+
+/// Parse sizes such as "512K", "64M" or "1G" into bytes
+fn parse_mem_bytes(lit: &LitStr) -> syn::Result<u64> {
+    let value = lit.value();
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+
+    let scale: u64 = match unit.trim() {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => {
+            return Err(Error::new(
+                lit.span(),
+                "Expected a size such as \"512K\", \"64M\" or \"1G\"",
+            ));
+        }
+    };
+
+    amount
+        .parse::<u64>()
+        .map_err(|_| Error::new(lit.span(), "Expected a whole number before the size unit"))?
+        .checked_mul(scale)
+        .ok_or_else(|| Error::new(lit.span(), "Size is too large"))
+}
 
 #[proc_macro_attribute]
-pub fn itest(_attr: TokenStream, item: TokenStream) -> TokenStream {
-    let input_fn: syn::ItemFn = syn::parse(item).unwrap();
+pub fn itest(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut args = ITestArgs::default();
+    let args_parser = syn::meta::parser(|meta| args.parse_meta(meta));
+    syn::parse_macro_input!(attr with args_parser);
+
+    let mut input_fn: syn::ItemFn = match syn::parse(item) {
+        Ok(v) => v,
+        Err(e) => {
+            return e.to_compile_error().into();
+        }
+    };
+
+    let resource_args = match take_resource_args(&mut input_fn) {
+        Ok(v) => v,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    let num_plain_args = resource_args.iter().filter(|r| r.is_none()).count();
+
+    let dependencies: Vec<String> = match take_dependencies(&mut input_fn) {
+        Ok(deps) => {
+            // tests are only skipped on failure, there is nothing to order
+            if let Some(dep) = deps.optional.first().or(deps.after.first()) {
+                return Error::new(dep.span(), "optional and #[after] dependencies are only supported by #[set_up]")
+                    .to_compile_error()
+                    .into();
+            }
+            deps.required.iter().map(|dep| dep.to_string()).collect()
+        }
+        Err(e) => return e.to_compile_error().into(),
+    };
+
     let fn_name = &input_fn.sig.ident;
     let fn_name_str = fn_name.to_string();
+    let is_async = input_fn.sig.asyncness.is_some();
 
     let span = input_fn.span().unwrap();
     let file = span.file();
     let line = span.line();
 
+    let returns_result = match returns_unit_result(&input_fn.sig.output) {
+        Ok(flag) => flag,
+        Err(e) => return e.to_compile_error().into(),
+    };
+
+
+    let ignore = match &args.ignore {
+        Some(reason) => quote! { Some(#reason) },
+        None => quote! { None },
+    };
+
+    let should_panic = match &args.should_panic {
+        None => quote! { ::itest_runner::ShouldPanic::No },
+        Some(None) => quote! { ::itest_runner::ShouldPanic::Yes },
+        Some(Some(expected)) => quote! { ::itest_runner::ShouldPanic::YesWithMessage(#expected) },
+    };
+
+    let timeout = optional_duration(args.timeout_ms);
+
+    // a test without cases is registered as a single case with no args
+    let cases = if args.cases.is_empty() {
+        vec![(fn_name_str.clone(), format!("__{}_itest_wrapper", fn_name), Vec::new())]
+    } else {
+        args.cases
+            .iter()
+            .map(|case| {
+                (
+                    format!("{}::{}", fn_name_str, case.name.value()),
+                    format!("__{}_{}_itest_wrapper", fn_name, case.ident_suffix()),
+                    case.args.clone(),
+                )
+            })
+            .collect()
+    };
+
+    let mut registrations = Vec::new();
+    for (test_name, wrapper_name, case_args) in cases {
+        let has_context_arg = match num_plain_args.checked_sub(case_args.len()) {
+            Some(0) => false,
+            Some(1) => true,
+            _ if case_args.is_empty() => {
+                return Error::new(input_fn.sig.span(), "Only TestContext arg permitted")
+                    .to_compile_error()
+                    .into();
+            }
+            _ => {
+                return Error::new(
+                    input_fn.sig.span(),
+                    format!(
+                        "case {} passes {} args, but the test takes {} (plus an optional TestContext)",
+                        test_name,
+                        case_args.len(),
+                        num_plain_args
+                    ),
+                )
+                .to_compile_error()
+                .into();
+            }
+        };
+
+        let wrapper_name = Ident::new(&wrapper_name, fn_name.span());
+
+        let wrapper_fn = create_test_wrapper(
+            fn_name,
+            &wrapper_name,
+            is_async,
+            has_context_arg,
+            &resource_args,
+            &case_args,
+            returns_result,
+        );
+
+        let test_fn = if is_async {
+            quote! { ::itest_runner::TestFn::Async(#wrapper_name) }
+        } else {
+            quote! { ::itest_runner::TestFn::Sync(#wrapper_name) }
+        };
+
+        registrations.push(quote! {
+            #wrapper_fn
+
+            ::itest_runner::submit! {
+                ::itest_runner::RegisteredITest{
+                    name: #test_name,
+                    test_fn: #test_fn,
+                    deps: &[#(#dependencies),*],
+                    ignore: #ignore,
+                    should_panic: #should_panic,
+                    timeout: #timeout,
+                    file: #file,
+                    line: #line,
+                }
+            }
+        });
+    }
+
     let expanded = quote! {
         #input_fn
 
-        ::itest_runner::submit! {
-            ::itest_runner::RegisteredITest{
-                name: #fn_name_str,
-                test_fn: #fn_name,
-                file: #file,
-                line: #line,
+        #(#registrations)*
+    };
+
+    expanded.into()
+}
+
+fn returns_unit_result(return_type: &ReturnType) -> Result<bool, Error> {
+    match return_type {
+        syn::ReturnType::Default => Ok(false),
+        syn::ReturnType::Type(_, t) => match &**t {
+            syn::Type::Tuple(tuple) if tuple.elems.is_empty() => Ok(false),
+            _ if is_unit_result(return_type)? => Ok(true),
+            _ => Err(Error::new(t.span(), "Expected () or Result<(), E> output")),
+        },
+    }
+}
+
+/// Remove `#[resource(Name)]` attributes from the test arguments.
+///
+/// Returns the component name for each resource argument and `None` for
+/// plain arguments (the test context and case args).
+fn take_resource_args(input_fn: &mut syn::ItemFn) -> Result<Vec<Option<Ident>>, Error> {
+    let mut resource_args = Vec::new();
+    for input in input_fn.sig.inputs.iter_mut() {
+        let mut component = None;
+        if let syn::FnArg::Typed(pat_type) = input {
+            let mut remaining = Vec::new();
+            for attr in pat_type.attrs.drain(..) {
+                if attr.path().is_ident("resource") {
+                    component = Some(attr.parse_args::<Ident>()?);
+                } else {
+                    remaining.push(attr);
+                }
+            }
+            pat_type.attrs = remaining;
+        }
+        resource_args.push(component);
+    }
+    Ok(resource_args)
+}
+
+/// Components named by `#[depends_on(..)]` and `#[after(..)]`
+#[derive(Default)]
+struct Dependencies {
+    required: Vec<Ident>,
+    optional: Vec<Ident>,
+    after: Vec<Ident>,
+}
+
+impl Dependencies {
+    fn contains(&self, dep: &Ident) -> bool {
+        self.required.contains(dep) || self.optional.contains(dep) || self.after.contains(dep)
+    }
+}
+
+/// Read the `#[depends_on(A, B, ...)]`, `#[depends_on(A, optional)]` and
+/// `#[after(A, ...)]` attributes, returning the components
+fn take_dependencies(input_fn: &mut syn::ItemFn) -> Result<Dependencies, Error> {
+    let mut dependencies = Dependencies::default();
+    let mut errors: Option<Error> = None;
+    let mut remaining = Vec::new();
+    for attr in input_fn.attrs.drain(..) {
+        let Some(kind) = dependency_attr(&attr) else {
+            remaining.push(attr);
+            continue;
+        };
+        let parsed = attr
+            .parse_args_with(Punctuated::<Ident, Token![,]>::parse_terminated)
+            .and_then(|deps| {
+                let mut deps: Vec<Ident> = deps.into_iter().collect();
+                let optional = kind == "depends_on" && deps.last().is_some_and(|d| d == "optional");
+                if optional {
+                    deps.pop();
+                }
+                if deps.is_empty() {
+                    Err(Error::new(attr.span(), "expected at least one component name"))
+                } else {
+                    Ok((deps, optional))
+                }
+            });
+        // keep the attribute, so its import stays used, but mark it handled
+        let mut handled = attr.clone();
+        if let syn::Meta::List(list) = &mut handled.meta {
+            list.tokens = quote! { _ };
+        }
+        remaining.push(handled);
+
+        match parsed {
+            Ok((deps, optional)) => {
+                for dep in deps {
+                    if dependencies.contains(&dep) {
+                        let e = Error::new(dep.span(), format!("duplicate dependency `{}`", dep));
+                        push_error(&mut errors, e);
+                    } else if kind == "after" {
+                        dependencies.after.push(dep);
+                    } else if optional {
+                        dependencies.optional.push(dep);
+                    } else {
+                        dependencies.required.push(dep);
+                    }
+                }
+            }
+            Err(e) => push_error(&mut errors, e),
+        }
+    }
+    input_fn.attrs = remaining;
+    match errors {
+        Some(e) => Err(e),
+        None => Ok(dependencies),
+    }
+}
+
+/// Read the `#[exclusive("lock", ...)]` attributes, returning the lock names
+fn take_exclusive(input_fn: &mut syn::ItemFn) -> Result<Vec<LitStr>, Error> {
+    let mut locks: Vec<LitStr> = Vec::new();
+    let mut errors: Option<Error> = None;
+    let mut remaining = Vec::new();
+    for attr in input_fn.attrs.drain(..) {
+        if attr.path().segments.last().is_none_or(|s| s.ident != "exclusive") {
+            remaining.push(attr);
+            continue;
+        }
+        let parsed = attr.parse_args_with(Punctuated::<LitStr, Token![,]>::parse_terminated);
+
+        // keep the attribute, so its import stays used, but mark it handled
+        let mut handled = attr.clone();
+        if let syn::Meta::List(list) = &mut handled.meta {
+            list.tokens = quote! { _ };
+        }
+        remaining.push(handled);
+
+        match parsed {
+            Ok(parsed) if parsed.is_empty() => {
+                push_error(&mut errors, Error::new(attr.span(), "expected at least one lock name"))
+            }
+            Ok(parsed) => {
+                for lock in parsed {
+                    if !locks.iter().any(|l| l.value() == lock.value()) {
+                        locks.push(lock);
+                    }
+                }
+            }
+            Err(e) => push_error(&mut errors, e),
+        }
+    }
+    input_fn.attrs = remaining;
+    match errors {
+        Some(e) => Err(e),
+        None => Ok(locks),
+    }
+}
+
+fn push_error(errors: &mut Option<Error>, e: Error) {
+    match errors {
+        Some(errors) => errors.combine(e),
+        None => *errors = Some(e),
+    }
+}
+
+/// `depends_on` or `after` when the attribute is one of those
+fn dependency_attr(attr: &syn::Attribute) -> Option<&'static str> {
+    let ident = &attr.path().segments.last()?.ident;
+    if ident == "depends_on" {
+        Some("depends_on")
+    } else if ident == "after" {
+        Some("after")
+    } else {
+        None
+    }
+}
+
+fn is_component_attr(attr: &syn::Attribute) -> bool {
+    attr.path()
+        .segments
+        .last()
+        .is_some_and(|s| s.ident == "set_up" || s.ident == "itest")
+}
+
+fn create_test_wrapper(
+    fn_name: &Ident,
+    wrapper_name: &Ident,
+    is_async: bool,
+    has_context_arg: bool,
+    resource_args: &[Option<Ident>],
+    case_args: &[syn::Expr],
+    returns_result: bool,
+) -> proc_macro2::TokenStream {
+    let mut plain_args = Vec::new();
+    if has_context_arg {
+        plain_args.push(quote! { ctx });
+    }
+    plain_args.extend(case_args.iter().map(|arg| quote! { #arg }));
+    let mut plain_args = plain_args.into_iter();
+
+    let mut fetch_resources = Vec::new();
+    let mut call_args = Vec::new();
+    for (idx, component) in resource_args.iter().enumerate() {
+        match component {
+            Some(component) => {
+                let var = Ident::new(&format!("__resource_{}", idx), component.span());
+                let component = component.to_string();
+                fetch_resources.push(quote! {
+                    let #var = match ctx.resource(#component) {
+                        Ok(resource) => resource,
+                        Err(e) => return ::itest_runner::TestResult::Err(Box::new(e)),
+                    };
+                });
+                call_args.push(quote! { #var });
             }
+            None => call_args.extend(plain_args.next()),
         }
+    }
+
+    let call = quote! { #fn_name(#(#call_args),*) };
+
+    let call = if is_async {
+        quote! { #call.await }
+    } else {
+        call
     };
 
-    expanded.into()
+    let result = if returns_result {
+        quote! { #call.map_err(|e| -> ::std::boxed::Box<dyn ::std::error::Error> { e.into() }) }
+    } else {
+        quote! {{ #call; ::itest_runner::TestResult::Ok(()) }}
+    };
+
+    if is_async {
+        quote! {
+            fn #wrapper_name(ctx: ::itest_runner::TestContext) -> ::itest_runner::TestFnOutput {
+                Box::pin(async move {
+                    #(#fetch_resources)*
+                    #result
+                })
+            }
+        }
+    } else {
+        quote! {
+            fn #wrapper_name(ctx: ::itest_runner::TestContext) -> ::itest_runner::TestResult {
+                #(#fetch_resources)*
+                #result
+            }
+        }
+    }
 }
 
 fn is_unit_result(return_type: &ReturnType) -> Result<bool, Error> {
+    Ok(matches!(
+        result_ok_type(return_type)?,
+        syn::Type::Tuple(tuple) if tuple.elems.is_empty()
+    ))
+}
+
+/// What a `#[set_up]` function hands back on success
+enum SetUpOutput {
+    Unit,
+    TearDown,
+    Provides,
+}
+
+fn set_up_output(return_type: &ReturnType) -> Result<SetUpOutput, Error> {
+    Ok(match result_ok_type(return_type)? {
+        syn::Type::Tuple(tuple) if tuple.elems.is_empty() => SetUpOutput::Unit,
+        syn::Type::Path(type_path)
+            if type_path
+                .path
+                .segments
+                .last()
+                .is_some_and(|s| s.ident == "Provides") =>
+        {
+            SetUpOutput::Provides
+        }
+        _ => SetUpOutput::TearDown,
+    })
+}
+
+fn result_ok_type(return_type: &ReturnType) -> Result<&syn::Type, Error> {
     match return_type {
         syn::ReturnType::Default => Err(Error::new(return_type.span(), "expect a return type")),
         syn::ReturnType::Type(_, t) => match &**t {
@@ -44,10 +684,7 @@ fn is_unit_result(return_type: &ReturnType) -> Result<bool, Error> {
                                 // First generic argument is the Ok type
                                 if let Some(syn::GenericArgument::Type(ok_type)) = args.args.first()
                                 {
-                                    Ok(match ok_type {
-                                        syn::Type::Tuple(tuple) if tuple.elems.is_empty() => true, // is unit,
-                                        _ => false, // It's a concrete type
-                                    })
+                                    Ok(ok_type)
                                 } else {
                                     Err(Error::new(
                                         segment.span(),
@@ -74,12 +711,19 @@ fn create_set_up_wrapper(
     wrapper_name: &Ident,
     is_async: bool,
     has_context_arg: bool,
-    is_unit_result: bool,
+    output: SetUpOutput,
 ) -> proc_macro2::TokenStream {
-    let ok_result = if is_unit_result {
-        quote! { Ok(None) }
-    } else {
-        quote! { Ok(Some(Box::new(teardown) as Box<dyn TearDown>)) }
+    let ok_result = match output {
+        SetUpOutput::Unit => quote! { Ok(None) },
+        SetUpOutput::TearDown => {
+            quote! { Ok(Some(Box::new(teardown) as Box<dyn ::itest_runner::TearDown>)) }
+        }
+        SetUpOutput::Provides => quote! { Ok(resources.provide(teardown)) },
+    };
+
+    let resources = match output {
+        SetUpOutput::Provides => quote! { let resources = ctx.clone(); },
+        _ => quote! {},
     };
 
     let call = if has_context_arg {
@@ -96,6 +740,7 @@ fn create_set_up_wrapper(
 
     quote! {
         fn #wrapper_name(ctx: ::itest_runner::Context) -> ::itest_runner::SetFnOutput {
+            #resources
             Box::pin(async move {
                 match #async_call {
                     Ok(teardown) => #ok_result,
@@ -108,7 +753,7 @@ fn create_set_up_wrapper(
 
 #[proc_macro_attribute]
 pub fn set_up(args: TokenStream, item: TokenStream) -> TokenStream {
-    let input_fn: syn::ItemFn = match syn::parse(item) {
+    let mut input_fn: syn::ItemFn = match syn::parse(item) {
         Ok(v) => v,
         Err(e) => {
             return e.to_compile_error().into();
@@ -116,8 +761,8 @@ pub fn set_up(args: TokenStream, item: TokenStream) -> TokenStream {
     };
 
     let is_async = input_fn.sig.asyncness.is_some();
-    let is_unit_result = match is_unit_result(&input_fn.sig.output) {
-        Ok(flag) => flag,
+    let output = match set_up_output(&input_fn.sig.output) {
+        Ok(output) => output,
         Err(e) => return e.to_compile_error().into(),
     };
 
@@ -135,29 +780,37 @@ pub fn set_up(args: TokenStream, item: TokenStream) -> TokenStream {
     let file = span.file();
     let line = span.line();
 
-    let name: syn::Ident = match syn::parse(args) {
+    let args: SetUpArgs = match syn::parse(args) {
         Ok(v) => v,
         Err(e) => {
             return e.to_compile_error().into();
         }
     };
 
-    let mut dependencies = Vec::new();
-    for attr in &input_fn.attrs {
-        if attr.path().is_ident("depends_on") {
-            let depends_on = attr.parse_args::<Ident>().unwrap().to_string();
-            dependencies.push(depends_on);
-        }
-    }
+    let timeout = optional_duration(args.timeout_ms);
+    let retry = args.retry();
+    let SetUpArgs {
+        name,
+        cpu,
+        mem_bytes,
+        ..
+    } = args;
+
+    let exclusive = match take_exclusive(&mut input_fn) {
+        Ok(locks) => locks,
+        Err(e) => return e.to_compile_error().into(),
+    };
+
+    let dependencies = match take_dependencies(&mut input_fn) {
+        Ok(deps) => deps,
+        Err(e) => return e.to_compile_error().into(),
+    };
 
     let setup_service = name.to_string();
 
-    let dep_strs: Vec<proc_macro2::TokenStream> = dependencies
-        .into_iter()
-        .map(|dep_str| {
-            quote! { #dep_str }
-        })
-        .collect();
+    let dep_strs = to_dep_strs(&dependencies.required);
+    let optional_dep_strs = to_dep_strs(&dependencies.optional);
+    let after_strs = to_dep_strs(&dependencies.after);
 
     let fn_name = &input_fn.sig.ident;
     let wrapper_name = Ident::new(&format!("__{}_set_up_wrapper", fn_name), fn_name.span());
@@ -167,7 +820,7 @@ pub fn set_up(args: TokenStream, item: TokenStream) -> TokenStream {
         &wrapper_name,
         is_async,
         has_context_arg,
-        is_unit_result,
+        output,
     );
 
     let expanded = quote! {
@@ -181,6 +834,12 @@ pub fn set_up(args: TokenStream, item: TokenStream) -> TokenStream {
                 name: #setup_service,
                 set_up_fn: #wrapper_name,
                 deps:  &[#(#dep_strs),*],
+                optional_deps: &[#(#optional_dep_strs),*],
+                after: &[#(#after_strs),*],
+                cost: ::itest_runner::Cost { cpu: #cpu, mem_bytes: #mem_bytes },
+                exclusive: &[#(#exclusive),*],
+                timeout: #timeout,
+                retry: #retry,
                 file: #file,
                 line: #line,
             }
@@ -191,8 +850,208 @@ pub fn set_up(args: TokenStream, item: TokenStream) -> TokenStream {
 }
 
 #[proc_macro_attribute]
-pub fn depends_on(_args: TokenStream, input: TokenStream) -> TokenStream {
-    // The depends attribute is handled by the set_up macro
-    // This is just a placeholder to make the attribute valid
-    input
+pub fn depends_on(args: TokenStream, input: TokenStream) -> TokenStream {
+    attr_placeholder("depends_on", args, input, validate_idents)
+}
+
+#[proc_macro_attribute]
+pub fn after(args: TokenStream, input: TokenStream) -> TokenStream {
+    attr_placeholder("after", args, input, validate_idents)
+}
+
+#[proc_macro_attribute]
+pub fn exclusive(args: TokenStream, input: TokenStream) -> TokenStream {
+    attr_placeholder("exclusive", args, input, |args| {
+        syn::parse::Parser::parse2(Punctuated::<LitStr, Token![,]>::parse_terminated, args)
+            .map(|_| ())
+    })
+}
+
+fn validate_idents(args: proc_macro2::TokenStream) -> syn::Result<()> {
+    syn::parse::Parser::parse2(Punctuated::<Ident, Token![,]>::parse_terminated, args).map(|_| ())
+}
+
+fn to_dep_strs(deps: &[Ident]) -> Vec<proc_macro2::TokenStream> {
+    deps.iter()
+        .map(|dep| {
+            let dep_str = dep.to_string();
+            quote! { #dep_str }
+        })
+        .collect()
+}
+
+fn attr_placeholder(
+    attr_name: &str,
+    args: TokenStream,
+    input: TokenStream,
+    validate: impl Fn(proc_macro2::TokenStream) -> syn::Result<()>,
+) -> TokenStream {
+    // These attributes are handled by the set_up and itest macros, which
+    // leave them behind as `#[depends_on(_)]`. Anything else was written above
+    // them, or there is nothing to handle it.
+    let args = proc_macro2::TokenStream::from(args);
+    let mut input_fn: syn::ItemFn = match syn::parse(input.clone()) {
+        Ok(v) => v,
+        Err(_) => {
+            let input = proc_macro2::TokenStream::from(input);
+            let warning = unused_attr_warning(attr_name, args.span());
+            return quote! { #warning #input }.into();
+        }
+    };
+
+    if is_handled_attr(&args) {
+        return quote! { #input_fn }.into();
+    }
+
+    if input_fn.attrs.iter().any(is_component_attr) {
+        // move it below the component attribute so it is seen there
+        let attr_ident = Ident::new(attr_name, proc_macro2::Span::call_site());
+        input_fn.attrs.push(syn::parse_quote! { #[#attr_ident(#args)] });
+        return quote! { #input_fn }.into();
+    }
+
+    if let Err(e) = validate(args.clone()) {
+        return e.to_compile_error().into();
+    }
+
+    let warning = unused_attr_warning(attr_name, args.span());
+    quote! { #warning #input_fn }.into()
+}
+
+fn is_handled_attr(args: &proc_macro2::TokenStream) -> bool {
+    args.to_string() == "_"
+}
+
+/// Stable proc macros can not emit warnings, so use of a deprecated item
+/// stands in for one.
+fn unused_attr_warning(attr_name: &str, span: proc_macro2::Span) -> proc_macro2::TokenStream {
+    let note = format!("#[{}] has no effect without #[set_up] or #[itest]", attr_name);
+    quote::quote_spanned! {span=>
+        const _: () = {
+            #[deprecated(note = #note)]
+            struct AttributeWithoutSetUp;
+            let _ = AttributeWithoutSetUp;
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use proc_macro2::Span;
+
+    fn lit(value: &str) -> LitStr {
+        LitStr::new(value, Span::call_site())
+    }
+
+    fn itest_args(tokens: proc_macro2::TokenStream) -> syn::Result<ITestArgs> {
+        let mut args = ITestArgs::default();
+        syn::parse::Parser::parse2(syn::meta::parser(|meta| args.parse_meta(meta)), tokens)?;
+        Ok(args)
+    }
+
+    fn names(idents: &[Ident]) -> Vec<String> {
+        idents.iter().map(|i| i.to_string()).collect()
+    }
+
+    fn errors(e: Error) -> Vec<String> {
+        e.into_iter().map(|e| e.to_string()).collect()
+    }
+
+    #[test]
+    fn durations_are_parsed() {
+        assert_eq!(500, parse_duration_ms(&lit("500ms")).unwrap());
+        assert_eq!(30_000, parse_duration_ms(&lit(" 30s ")).unwrap());
+        assert_eq!(300_000, parse_duration_ms(&lit("5m")).unwrap());
+        assert_eq!(3_600_000, parse_duration_ms(&lit("1h")).unwrap());
+
+        let err = |value| parse_duration_ms(&lit(value)).unwrap_err().to_string();
+        assert_eq!("Expected a duration such as \"500ms\", \"30s\", \"5m\" or \"1h\"", err("30"));
+        assert_eq!("Expected a duration such as \"500ms\", \"30s\", \"5m\" or \"1h\"", err("1d"));
+        assert_eq!("Expected a whole number before the duration unit", err("ms"));
+        assert_eq!("Duration is too large", err("18446744073709551615h"));
+    }
+
+    #[test]
+    fn sizes_are_parsed() {
+        assert_eq!(7, parse_mem_bytes(&lit("7")).unwrap());
+        assert_eq!(512 << 10, parse_mem_bytes(&lit("512K")).unwrap());
+        assert_eq!(1 << 30, parse_mem_bytes(&lit("1G")).unwrap());
+        assert_eq!(2 << 40, parse_mem_bytes(&lit("2T")).unwrap());
+
+        let err = |value| parse_mem_bytes(&lit(value)).unwrap_err().to_string();
+        assert_eq!("Expected a size such as \"512K\", \"64M\" or \"1G\"", err("1X"));
+        assert_eq!("Expected a whole number before the size unit", err("G"));
+        assert_eq!("Size is too large", err("99999999T"));
+    }
+
+    #[test]
+    fn case_names_must_map_to_distinct_wrappers() {
+        let args = itest_args(quote! { case(name = "a-1", args(1)), case(name = "b", args(2)) }).unwrap();
+        assert_eq!(vec!["a_1", "b"], args.cases.iter().map(TestCase::ident_suffix).collect::<Vec<_>>());
+
+        let err = |tokens| itest_args(tokens).err().unwrap().to_string();
+        assert_eq!(
+            "Duplicate case name",
+            err(quote! { case(name = "a", args(1)), case(name = "a", args(2)) })
+        );
+        assert_eq!(
+            "Case name \"a_1\" clashes with \"a-1\", use names that differ in letters or digits",
+            err(quote! { case(name = "a-1", args(1)), case(name = "a_1", args(2)) })
+        );
+        assert_eq!("case requires a name", err(quote! { case(args(1)) }));
+    }
+
+    #[test]
+    fn dependencies_are_read_from_the_attributes() {
+        let mut input_fn: syn::ItemFn = syn::parse_quote! {
+            #[depends_on(A, B)]
+            #[depends_on(C, optional)]
+            #[after(D)]
+            #[inline]
+            fn set_up() {}
+        };
+        let deps = take_dependencies(&mut input_fn).unwrap();
+        assert_eq!(vec!["A", "B"], names(&deps.required));
+        assert_eq!(vec!["C"], names(&deps.optional));
+        assert_eq!(vec!["D"], names(&deps.after));
+
+        // the attributes stay, marked as handled
+        let attrs: Vec<String> = input_fn.attrs.iter().map(|a| quote! { #a }.to_string()).collect();
+        assert_eq!(vec!["# [depends_on (_)]", "# [depends_on (_)]", "# [after (_)]", "# [inline]"], attrs);
+    }
+
+    #[test]
+    fn malformed_dependencies_are_reported() {
+        let mut input_fn: syn::ItemFn = syn::parse_quote! {
+            #[depends_on()]
+            #[depends_on(A, "b")]
+            #[depends_on(C)]
+            #[after(C)]
+            fn set_up() {}
+        };
+        let err = take_dependencies(&mut input_fn).err().unwrap();
+        assert_eq!(
+            vec![
+                "expected at least one component name",
+                "expected identifier",
+                "duplicate dependency `C`",
+            ],
+            errors(err)
+        );
+    }
+
+    #[test]
+    fn resource_args_are_taken_from_the_inputs() {
+        let mut input_fn: syn::ItemFn = syn::parse_quote! {
+            fn test(ctx: TestContext, #[resource(Postgres)] db: Arc<PostgresDb>) {}
+        };
+        let resource_args = take_resource_args(&mut input_fn).unwrap();
+        let components: Vec<Option<String>> = resource_args.iter().map(|r| r.as_ref().map(Ident::to_string)).collect();
+        assert_eq!(vec![None, Some("Postgres".to_owned())], components);
+        assert_eq!(
+            quote! { fn test(ctx: TestContext, db: Arc<PostgresDb>) {} }.to_string(),
+            quote! { #input_fn }.to_string()
+        );
+    }
 }
@@ -0,0 +1,179 @@
+use std::collections::HashSet;
+
+/// Resources a set up uses while it runs, as declared with
+/// `#[set_up(Name, cost(cpu = 2, mem = "1G"))]`
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Cost {
+    pub cpu: u32,
+    pub mem_bytes: u64,
+}
+
+impl Cost {
+    pub const NONE: Cost = Cost {
+        cpu: 0,
+        mem_bytes: 0,
+    };
+}
+
+/// Limits on the combined cost of the set ups running at the same time.
+///
+/// A set up that costs more than the whole budget still runs, but only once
+/// nothing else is running.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Budget {
+    cpu: Option<u32>,
+    mem_bytes: Option<u64>,
+}
+
+impl Budget {
+    /// No limits, only the number of workers restricts how many set ups run
+    pub fn unlimited() -> Budget {
+        Budget::default()
+    }
+
+    pub fn with_cpu(self, cpu: u32) -> Budget {
+        Budget {
+            cpu: Some(cpu),
+            ..self
+        }
+    }
+
+    pub fn with_mem_bytes(self, mem_bytes: u64) -> Budget {
+        Budget {
+            mem_bytes: Some(mem_bytes),
+            ..self
+        }
+    }
+}
+
+/// Keeps track of what the running set ups are using
+pub(crate) struct Allocator {
+    budget: Budget,
+    max_running: usize,
+    running: usize,
+    in_use: Cost,
+    locks: HashSet<&'static str>,
+}
+
+impl Allocator {
+    pub fn new(budget: Budget, max_running: usize) -> Allocator {
+        Allocator {
+            budget,
+            max_running,
+            running: 0,
+            in_use: Cost::NONE,
+            locks: HashSet::new(),
+        }
+    }
+
+    /// Reserve the cost and locks, if they are available
+    pub fn try_acquire(&mut self, cost: Cost, locks: &[&'static str]) -> bool {
+        if self.running >= self.max_running || locks.iter().any(|l| self.locks.contains(l)) {
+            return false;
+        }
+
+        // a sum that overflows does not fit either, it can only happen while
+        // something else is running
+        let (Some(cpu), Some(mem_bytes)) = (
+            self.in_use.cpu.checked_add(cost.cpu),
+            self.in_use.mem_bytes.checked_add(cost.mem_bytes),
+        ) else {
+            return false;
+        };
+        let fits = self.budget.cpu.is_none_or(|max| cpu <= max)
+            && self.budget.mem_bytes.is_none_or(|max| mem_bytes <= max);
+        if !fits && self.running > 0 {
+            return false;
+        }
+
+        self.running += 1;
+        self.in_use = Cost { cpu, mem_bytes };
+        self.locks.extend(locks);
+        true
+    }
+
+    pub fn release(&mut self, cost: Cost, locks: &[&'static str]) {
+        self.running -= 1;
+        self.in_use.cpu -= cost.cpu;
+        self.in_use.mem_bytes -= cost.mem_bytes;
+        for lock in locks {
+            self.locks.remove(lock);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const GIG: u64 = 1 << 30;
+
+    #[test]
+    fn tasks_are_only_started_while_they_fit() {
+        let mut alloc = Allocator::new(Budget::unlimited().with_cpu(4).with_mem_bytes(2 * GIG), 10);
+        let heavy = Cost {
+            cpu: 3,
+            mem_bytes: GIG,
+        };
+
+        assert!(alloc.try_acquire(heavy, &[]));
+        assert!(!alloc.try_acquire(heavy, &[]));
+        assert!(alloc.try_acquire(Cost { cpu: 1, mem_bytes: GIG }, &[]));
+        assert!(!alloc.try_acquire(Cost { cpu: 0, mem_bytes: 1 }, &[]));
+        assert!(alloc.try_acquire(Cost::NONE, &[]));
+
+        alloc.release(heavy, &[]);
+        assert!(alloc.try_acquire(heavy, &[]));
+    }
+
+    #[test]
+    fn exclusive_locks_are_held_by_one_task() {
+        let mut alloc = Allocator::new(Budget::unlimited(), 10);
+        assert!(alloc.try_acquire(Cost::NONE, &["cargo"]));
+        assert!(!alloc.try_acquire(Cost::NONE, &["docker", "cargo"]));
+        assert!(alloc.try_acquire(Cost::NONE, &["docker"]));
+
+        alloc.release(Cost::NONE, &["cargo"]);
+        assert!(alloc.try_acquire(Cost::NONE, &["cargo"]));
+    }
+
+    #[test]
+    fn oversized_tasks_run_alone() {
+        let mut alloc = Allocator::new(Budget::unlimited().with_cpu(2), 10);
+        let oversized = Cost {
+            cpu: 8,
+            mem_bytes: 0,
+        };
+
+        assert!(alloc.try_acquire(Cost { cpu: 1, mem_bytes: 0 }, &[]));
+        assert!(!alloc.try_acquire(oversized, &[]));
+
+        alloc.release(Cost { cpu: 1, mem_bytes: 0 }, &[]);
+        assert!(alloc.try_acquire(oversized, &[]));
+        assert!(!alloc.try_acquire(Cost::NONE, &[]));
+    }
+
+    #[test]
+    fn huge_costs_do_not_overflow() {
+        let mut alloc = Allocator::new(Budget::unlimited().with_mem_bytes(u64::MAX), 10);
+        let huge = Cost {
+            cpu: u32::MAX,
+            mem_bytes: u64::MAX - 1,
+        };
+
+        assert!(alloc.try_acquire(huge, &[]));
+        assert!(!alloc.try_acquire(Cost { cpu: 1, mem_bytes: 0 }, &[]));
+        assert!(!alloc.try_acquire(Cost { cpu: 0, mem_bytes: 2 }, &[]));
+        assert!(alloc.try_acquire(Cost { cpu: 0, mem_bytes: 1 }, &[]));
+
+        alloc.release(huge, &[]);
+        assert!(alloc.try_acquire(Cost { cpu: 1, mem_bytes: GIG }, &[]));
+    }
+
+    #[test]
+    fn worker_limit_is_respected() {
+        let mut alloc = Allocator::new(Budget::unlimited(), 1);
+        assert!(alloc.try_acquire(Cost::NONE, &[]));
+        assert!(!alloc.try_acquire(Cost::NONE, &[]));
+    }
+}
@@ -0,0 +1,112 @@
+use std::ffi::OsString;
+
+use crate::{graph::GraphFormat, SetUpConcurrency};
+
+/// Options handled by the runner itself rather than by libtest
+#[derive(Debug, Default, PartialEq)]
+pub struct RunnerArgs {
+    pub graph: Option<GraphFormat>,
+    pub explain: Option<String>,
+    pub set_up_concurrency: Option<SetUpConcurrency>,
+    pub keep_env: bool,
+    pub tear_down_env: bool,
+    pub env_only: bool,
+}
+
+/// Split our own options from the ones that are passed on to libtest.
+///
+/// Accepts both `--opt value` and `--opt=value`.
+pub fn parse(args: impl IntoIterator<Item = OsString>) -> Result<(RunnerArgs, Vec<OsString>), String> {
+    let mut runner_args = RunnerArgs::default();
+    let mut remaining = Vec::new();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        let Some(arg_str) = arg.to_str() else {
+            remaining.push(arg);
+            continue;
+        };
+
+        let (opt, inline_value) = match arg_str.split_once('=') {
+            Some((opt, value)) => (opt, Some(value.to_owned())),
+            None => (arg_str, None),
+        };
+
+        match opt {
+            "--graph" => {
+                let value = option_value(opt, inline_value, &mut args)?;
+                runner_args.graph = Some(value.parse()?);
+            }
+            "--explain" => {
+                runner_args.explain = Some(option_value(opt, inline_value, &mut args)?);
+            }
+            "--set-up-concurrency" => {
+                let value = option_value(opt, inline_value, &mut args)?;
+                runner_args.set_up_concurrency = Some(value.parse()?);
+            }
+            "--keep-env" => runner_args.keep_env = true,
+            "--tear-down-env" => runner_args.tear_down_env = true,
+            "--env-only" => runner_args.env_only = true,
+            // everything after `--` is a libtest filter
+            "--" => {
+                remaining.push(arg);
+                remaining.extend(args);
+                break;
+            }
+            _ => remaining.push(arg),
+        }
+    }
+
+    Ok((runner_args, remaining))
+}
+
+fn option_value(
+    opt: &str,
+    inline_value: Option<String>,
+    args: &mut impl Iterator<Item = OsString>,
+) -> Result<String, String> {
+    inline_value
+        .or_else(|| args.next().and_then(|v| v.into_string().ok()))
+        .ok_or_else(|| format!("{} requires a value", opt))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::num::NonZeroUsize;
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn runner_options_are_removed_from_libtest_args() {
+        let (runner_args, remaining) =
+            parse(args(&["itest", "--graph=dot", "--explain", "Server", "--exact", "--set-up-concurrency=1", "--keep-env", "foo"])).unwrap();
+
+        assert_eq!(
+            RunnerArgs {
+                graph: Some(GraphFormat::Dot),
+                explain: Some("Server".to_owned()),
+                set_up_concurrency: Some(SetUpConcurrency::Workers(NonZeroUsize::MIN)),
+                keep_env: true,
+                tear_down_env: false,
+                env_only: false,
+            },
+            runner_args
+        );
+        assert_eq!(args(&["itest", "--exact", "foo"]), remaining);
+    }
+
+    #[test]
+    fn invalid_runner_options_are_reported() {
+        assert_eq!(
+            Err("unknown graph format `svg`, expected dot or mermaid".to_owned()),
+            parse(args(&["itest", "--graph", "svg"]))
+        );
+        assert_eq!(
+            Err("--explain requires a value".to_owned()),
+            parse(args(&["itest", "--explain"]))
+        );
+    }
+}
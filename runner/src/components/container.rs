@@ -1,6 +1,46 @@
-use crate::TearDown;
+use crate::{
+    Context, KeepHandle, TearDown,
+    components::wait::{self, Probe},
+};
 use async_trait::async_trait;
-use testcontainers::{ContainerAsync, GenericImage};
+use testcontainers::{ContainerAsync, ContainerRequest, GenericImage, runners::AsyncRunner};
+
+/// Starts a container, logs its output and waits for it to be ready
+pub struct ContainerSetUp {
+    request: ContainerRequest<GenericImage>,
+    probes: Vec<Probe>,
+}
+
+impl ContainerSetUp {
+    pub fn new(request: impl Into<ContainerRequest<GenericImage>>) -> ContainerSetUp {
+        ContainerSetUp {
+            request: request.into(),
+            probes: Vec::new(),
+        }
+    }
+
+    /// Only count the container as started once the probe passes, probes
+    /// are waited for in the order they are added
+    pub fn wait_for(mut self, probe: Probe) -> ContainerSetUp {
+        self.probes.push(probe);
+        self
+    }
+
+    /// Start the container, its stdout and stderr are written to the
+    /// component's log files
+    pub async fn start(self, ctx: &Context) -> Result<ContainerTearDown, Box<dyn std::error::Error>> {
+        let container = self.request.start().await?;
+        ctx.monitor_async("stdout", container.stdout(true));
+        ctx.monitor_async("stderr", container.stderr(true));
+
+        let mut tear_down = ContainerTearDown::new(container);
+        if let Err(e) = wait::wait_all(&self.probes).await {
+            let _ = tear_down.tear_down().await;
+            return Err(e.into());
+        }
+        Ok(tear_down)
+    }
+}
 
 pub struct ContainerTearDown {
     container: Option<ContainerAsync<GenericImage>>,
@@ -23,4 +63,15 @@ impl TearDown for ContainerTearDown {
         }
         Ok(())
     }
+
+    fn keep_handles(&self) -> Option<Vec<KeepHandle>> {
+        let handles = self
+            .container
+            .iter()
+            .map(|container| KeepHandle::Container {
+                id: container.id().to_owned(),
+            })
+            .collect();
+        Some(handles)
+    }
 }
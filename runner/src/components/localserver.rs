@@ -5,12 +5,16 @@ use std::{
 
 use async_trait::async_trait;
 
-use crate::{Context, TearDown};
+use crate::{
+    Context, KeepHandle, TearDown,
+    components::wait::{self, Probe},
+};
 
 pub struct LocalServerSetUp {
     name: String,
     args: Vec<String>,
     envs: Vec<(String, String)>,
+    probes: Vec<Probe>,
 }
 
 impl LocalServerSetUp {
@@ -19,6 +23,7 @@ impl LocalServerSetUp {
             name: name.to_owned(),
             args: Vec::new(),
             envs: Vec::new(),
+            probes: Vec::new(),
         }
     }
 
@@ -27,6 +32,7 @@ impl LocalServerSetUp {
             name: self.name,
             args: args.iter().map(|i| i.to_string()).collect(),
             envs: self.envs,
+            probes: self.probes,
         }
     }
 
@@ -38,10 +44,20 @@ impl LocalServerSetUp {
                 .iter()
                 .map(|(k, v)| (k.to_string(), v.to_string()))
                 .collect(),
+            probes: self.probes,
         }
     }
 
-    pub fn start(self, ctx: Context) -> Result<impl TearDown, Box<dyn std::error::Error>> {
+    /// Only count the server as started once the probe passes, probes are
+    /// waited for in the order they are added
+    pub fn wait_for(mut self, probe: Probe) -> LocalServerSetUp {
+        self.probes.push(probe);
+        self
+    }
+
+    /// Start the server and wait for its probes without blocking the runtime,
+    /// so set up timeouts and interrupts still apply
+    pub async fn start(self, ctx: Context) -> Result<impl TearDown, Box<dyn std::error::Error>> {
         let binary = ctx.workspace_binary_path(&self.name);
 
         let stdout_file = File::create(ctx.log_file_path("stdout"))?;
@@ -54,7 +70,12 @@ impl LocalServerSetUp {
             .args(&self.args)
             .spawn()?;
 
-        Ok(LocalRunnerComponent { child })
+        let mut component = LocalRunnerComponent { child };
+        if let Err(e) = wait::wait_all(&self.probes).await {
+            let _ = component.stop();
+            return Err(e.into());
+        }
+        Ok(component)
     }
 }
 
@@ -62,10 +83,25 @@ pub struct LocalRunnerComponent {
     child: Child,
 }
 
+impl LocalRunnerComponent {
+    /// Kill the server and reap it, so no zombie process is left behind
+    fn stop(&mut self) -> std::io::Result<()> {
+        self.child.kill()?;
+        self.child.wait()?;
+        Ok(())
+    }
+}
+
 #[async_trait]
 impl TearDown for LocalRunnerComponent {
     async fn tear_down(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        self.child.kill()?;
+        self.stop()?;
         Ok(())
     }
+
+    fn keep_handles(&self) -> Option<Vec<KeepHandle>> {
+        Some(vec![KeepHandle::Process {
+            pid: self.child.id(),
+        }])
+    }
 }
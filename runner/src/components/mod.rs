@@ -1,3 +1,4 @@
 pub mod container;
 pub mod localserver;
 pub mod localcli;
+pub mod wait;
@@ -0,0 +1,331 @@
+//! Probes that wait until a component is ready to be used.
+//!
+//! ```ignore
+//! LocalServerSetUp::new("example-server")
+//!     .wait_for(Probe::tcp("localhost:3000"))
+//!     .wait_for(Probe::log_line(ctx.log_file_path("stdout"), "listening on")?)
+//!     .start(ctx)
+//!     .await
+//! ```
+use std::{
+    fmt,
+    fs,
+    io::{Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    path::PathBuf,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use regex::Regex;
+
+/// A readiness check that is polled until it passes or times out
+#[derive(Debug, Clone)]
+pub struct Probe {
+    check: Check,
+    timeout: Duration,
+    poll_interval: Duration,
+}
+
+#[derive(Debug, Clone)]
+enum Check {
+    Tcp(String),
+    HttpStatus { url: String, status: u16 },
+    LogLine { path: PathBuf, pattern: Regex },
+    FileExists(PathBuf),
+    Command { program: String, args: Vec<String> },
+}
+
+/// The probe did not pass within its timeout
+#[derive(Debug)]
+pub struct WaitError {
+    probe: String,
+    timeout: Duration,
+    last_failure: String,
+}
+
+impl fmt::Display for WaitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Timed out after {:.02}s waiting for {}: {}",
+            self.timeout.as_millis() as f64 / 1000.0,
+            self.probe,
+            self.last_failure
+        )
+    }
+}
+
+impl std::error::Error for WaitError {}
+
+impl Probe {
+    fn new(check: Check) -> Probe {
+        Probe {
+            check,
+            timeout: Duration::from_secs(30),
+            poll_interval: Duration::from_millis(250),
+        }
+    }
+
+    /// A TCP connection to `addr`, such as `localhost:3000`, succeeds
+    pub fn tcp(addr: &str) -> Probe {
+        Probe::new(Check::Tcp(addr.to_owned()))
+    }
+
+    /// A `GET` of the (plain `http://`) url answers with the status
+    pub fn http_status(url: &str, status: u16) -> Probe {
+        Probe::new(Check::HttpStatus {
+            url: url.to_owned(),
+            status,
+        })
+    }
+
+    /// A line of the log file matches the regex, usually a file from
+    /// [`crate::Context::log_file_path`]
+    pub fn log_line(path: impl Into<PathBuf>, pattern: &str) -> Result<Probe, regex::Error> {
+        Ok(Probe::new(Check::LogLine {
+            path: path.into(),
+            pattern: Regex::new(pattern)?,
+        }))
+    }
+
+    pub fn file_exists(path: impl Into<PathBuf>) -> Probe {
+        Probe::new(Check::FileExists(path.into()))
+    }
+
+    /// The command exits with status 0
+    pub fn command(program: &str, args: &[&str]) -> Probe {
+        Probe::new(Check::Command {
+            program: program.to_owned(),
+            args: args.iter().map(|a| a.to_string()).collect(),
+        })
+    }
+
+    /// How long to keep polling, 30s by default
+    pub fn with_timeout(self, timeout: Duration) -> Probe {
+        Probe { timeout, ..self }
+    }
+
+    /// How long to wait between checks, 250ms by default
+    pub fn with_poll_interval(self, poll_interval: Duration) -> Probe {
+        Probe {
+            poll_interval,
+            ..self
+        }
+    }
+
+    /// Poll until the probe passes, blocking the current thread
+    pub fn wait_blocking(&self) -> Result<(), WaitError> {
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            let last_failure = match self.check.run() {
+                Ok(()) => return Ok(()),
+                Err(failure) => failure,
+            };
+            if Instant::now() + self.poll_interval > deadline {
+                return Err(self.timed_out(last_failure));
+            }
+            thread::sleep(self.poll_interval);
+        }
+    }
+
+    /// Poll until the probe passes
+    pub async fn wait(&self) -> Result<(), WaitError> {
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            let check = self.check.clone();
+            let last_failure = match tokio::task::spawn_blocking(move || check.run()).await {
+                Ok(Ok(())) => return Ok(()),
+                Ok(Err(failure)) => failure,
+                Err(e) => format!("probe panicked: {}", e),
+            };
+            if Instant::now() + self.poll_interval > deadline {
+                return Err(self.timed_out(last_failure));
+            }
+            tokio::time::sleep(self.poll_interval).await;
+        }
+    }
+
+    fn timed_out(&self, last_failure: String) -> WaitError {
+        WaitError {
+            probe: self.check.to_string(),
+            timeout: self.timeout,
+            last_failure,
+        }
+    }
+}
+
+/// Wait for every probe in turn
+pub(crate) async fn wait_all(probes: &[Probe]) -> Result<(), WaitError> {
+    for probe in probes {
+        probe.wait().await?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Check::Tcp(addr) => write!(f, "tcp {}", addr),
+            Check::HttpStatus { url, status } => write!(f, "http status {} from {}", status, url),
+            Check::LogLine { path, pattern } => write!(f, "log line /{}/ in {}", pattern, path.display()),
+            Check::FileExists(path) => write!(f, "file {}", path.display()),
+            Check::Command { program, args } => write!(f, "command `{} {}`", program, args.join(" ")),
+        }
+    }
+}
+
+/// Longest a single check may take, so a hanging peer can not stall polling
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+impl Check {
+    fn run(&self) -> Result<(), String> {
+        match self {
+            Check::Tcp(addr) => connect(addr).map(|_| ()),
+            Check::HttpStatus { url, status } => {
+                let actual = http_get_status(url)?;
+                if actual == *status {
+                    Ok(())
+                } else {
+                    Err(format!("got status {}", actual))
+                }
+            }
+            Check::LogLine { path, pattern } => {
+                let contents = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+                if String::from_utf8_lossy(&contents).lines().any(|l| pattern.is_match(l)) {
+                    Ok(())
+                } else {
+                    Err("no matching line".to_owned())
+                }
+            }
+            Check::FileExists(path) => {
+                if path.exists() {
+                    Ok(())
+                } else {
+                    Err("does not exist".to_owned())
+                }
+            }
+            Check::Command { program, args } => {
+                let status = Command::new(program)
+                    .args(args)
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .status()
+                    .map_err(|e| e.to_string())?;
+                if status.success() {
+                    Ok(())
+                } else {
+                    Err(status.to_string())
+                }
+            }
+        }
+    }
+}
+
+fn connect(addr: &str) -> Result<TcpStream, String> {
+    let addrs = addr.to_socket_addrs().map_err(|e| e.to_string())?;
+    let mut last_err = format!("{} did not resolve", addr);
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, CHECK_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_err = e.to_string(),
+        }
+    }
+    Err(last_err)
+}
+
+fn http_get_status(url: &str) -> Result<u16, String> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| format!("only http:// urls are supported, got {}", url))?;
+    let (host, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, "/"),
+    };
+    let addr = if host.contains(':') {
+        host.to_owned()
+    } else {
+        format!("{}:80", host)
+    };
+
+    let mut stream = connect(&addr)?;
+    stream
+        .set_read_timeout(Some(CHECK_TIMEOUT))
+        .map_err(|e| e.to_string())?;
+    write!(
+        stream,
+        "GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        path, host
+    )
+    .map_err(|e| e.to_string())?;
+
+    let mut status_line = [0u8; 12];
+    stream
+        .read_exact(&mut status_line)
+        .map_err(|e| e.to_string())?;
+    parse_status(&status_line).ok_or_else(|| "malformed http response".to_owned())
+}
+
+/// Status code from the start of a response, `HTTP/1.1 200`
+fn parse_status(status_line: &[u8]) -> Option<u16> {
+    let status_line = std::str::from_utf8(status_line).ok()?;
+    let (version, code) = status_line.split_once(' ')?;
+    if !version.starts_with("HTTP/") {
+        return None;
+    }
+    code.get(..3)?.parse().ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::TcpListener;
+
+    fn quick(probe: Probe) -> Probe {
+        probe
+            .with_timeout(Duration::from_millis(100))
+            .with_poll_interval(Duration::from_millis(10))
+    }
+
+    #[test]
+    fn probes_pass_once_ready() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        assert!(quick(Probe::tcp(&addr)).wait_blocking().is_ok());
+
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("server.log");
+        fs::write(&log, "starting\nlistening on 3000\n").unwrap();
+        assert!(quick(Probe::log_line(&log, r"listening on \d+").unwrap()).wait_blocking().is_ok());
+        assert!(quick(Probe::file_exists(&log)).wait_blocking().is_ok());
+        assert!(quick(Probe::command("true", &[])).wait_blocking().is_ok());
+    }
+
+    #[test]
+    fn timeouts_report_the_last_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("server.log");
+        fs::write(&log, "starting\n").unwrap();
+
+        let err = quick(Probe::log_line(&log, "listening").unwrap()).wait_blocking().unwrap_err();
+        assert_eq!(
+            format!("Timed out after 0.10s waiting for log line /listening/ in {}: no matching line", log.display()),
+            err.to_string()
+        );
+        assert!(quick(Probe::command("false", &[])).wait_blocking().is_err());
+    }
+
+    #[test]
+    fn invalid_log_patterns_are_rejected_up_front() {
+        assert!(Probe::log_line("server.log", "(").is_err());
+    }
+
+    #[test]
+    fn http_status_lines_are_parsed() {
+        assert_eq!(Some(200), parse_status(b"HTTP/1.1 200"));
+        assert_eq!(Some(503), parse_status(b"HTTP/1.0 503"));
+        assert_eq!(None, parse_status(b"SSH-2.0-Open"));
+    }
+}
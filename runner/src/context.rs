@@ -1,13 +1,20 @@
 use std::{
+    any::Any,
     fmt, fs,
     path::{Path, PathBuf},
     pin::Pin,
-    sync::Arc,
+    sync::{Arc, Mutex},
 };
 
 use dashmap::DashMap;
 use tokio::io::AsyncBufRead;
 
+use crate::{
+    TearDown,
+    logs::LogFiles,
+    resource::{Provides, ResourceError, Resources},
+};
+
 #[derive(Clone)]
 pub struct Param {
     raw: String,
@@ -27,6 +34,8 @@ impl fmt::Debug for Param {
 
 pub struct GlobalContext {
     params: Arc<DashMap<String, Param>>,
+    resources: Resources,
+    log_files: LogFiles,
     workspace_root_dir: PathBuf,
 }
 
@@ -39,6 +48,8 @@ impl GlobalContext {
         fs::create_dir_all(&log_dir).unwrap();
         Self {
             params: Arc::new(DashMap::new()),
+            resources: Resources::default(),
+            log_files: LogFiles::default(),
             workspace_root_dir: workspace_root_dir.to_path_buf(),
         }
     }
@@ -46,11 +57,57 @@ impl GlobalContext {
     pub(crate) fn create_component_context(&mut self, name: &str) -> Context {
         Context {
             params: self.params.clone(),
+            resources: self.resources.clone(),
+            log_files: self.log_files.clone(),
+            tear_downs: Arc::default(),
             workspace_root_dir: self.workspace_root_dir.clone(),
             component_name: name.to_owned(),
         }
     }
 
+    pub(crate) fn create_test_context(&self, name: &str) -> TestContext {
+        TestContext {
+            params: self.params.clone(),
+            resources: self.resources.clone(),
+            log_files: self.log_files.clone(),
+            workspace_root_dir: self.workspace_root_dir.clone(),
+            test_name: name.to_owned(),
+        }
+    }
+
+    pub(crate) fn workspace_root_dir(&self) -> &Path {
+        &self.workspace_root_dir
+    }
+
+    pub(crate) fn log_files(&self) -> LogFiles {
+        self.log_files.clone()
+    }
+
+    /// Params set by the component, as `(key, value)`
+    pub(crate) fn component_params(&self, name: &str) -> Vec<(String, String)> {
+        let prefix = format!("{}.", clean_name(name));
+        let mut params: Vec<(String, String)> = self
+            .params
+            .iter()
+            .filter(|p| p.key().starts_with(&prefix))
+            .map(|p| (p.key().clone(), p.value().raw.clone()))
+            .collect();
+        params.sort();
+        params
+    }
+
+    pub(crate) fn provides_resource(&self, name: &str) -> bool {
+        self.resources.contains(name)
+    }
+
+    /// Use a component kept by an earlier run instead of setting it up
+    pub(crate) fn reuse_component(&mut self, name: &str, params: &[(String, String)]) {
+        for (key, value) in params {
+            self.set_global_param(key, value);
+        }
+        self.resources.mark_kept(name);
+    }
+
     pub fn set_global_param(&mut self, key: &str, value: &str) {
         self.params.insert(
             key.to_owned(),
@@ -61,30 +118,55 @@ impl GlobalContext {
     }
 }
 
+#[derive(Clone)]
 pub struct Context {
     params: Arc<DashMap<String, Param>>,
+    resources: Resources,
+    log_files: LogFiles,
+    tear_downs: Arc<Mutex<Vec<Box<dyn TearDown>>>>,
     workspace_root_dir: PathBuf,
     component_name: String,
 }
 
+fn clean_name(name: &str) -> String {
+    let clean_name = name.replace("/", "_");
+    clean_name.trim().to_string()
+}
+
+fn log_dir(workspace_root_dir: &Path) -> PathBuf {
+    let mut log_dir = workspace_root_dir.to_owned();
+    log_dir.push("target");
+    log_dir.push("itest");
+    log_dir.push("logs");
+    fs::create_dir_all(&log_dir).unwrap();
+    log_dir
+}
+
+fn workspace_binary_path(workspace_root_dir: &Path, binary_name: &str) -> PathBuf {
+    let profile = if cfg!(debug_assertions) {
+        "debug"
+    } else {
+        "release"
+    };
+    let mut path = workspace_root_dir.to_path_buf();
+    path.push("target");
+    path.push(profile);
+    path.push(binary_name);
+    path
+}
+
 impl Context {
     fn clean_component_name(&self) -> String {
-        let clean_name = self.component_name.replace("/", "_");
-        clean_name.trim().to_string()
+        clean_name(&self.component_name)
     }
 
     fn log_dir(&self) -> PathBuf {
-        let mut log_dir = self.workspace_root_dir.to_owned();
-        log_dir.push("target");
-        log_dir.push("itest");
-        log_dir.push("logs");
-        fs::create_dir_all(&log_dir).unwrap();
-        log_dir
+        log_dir(&self.workspace_root_dir)
     }
 
     pub fn monitor_async(&self, name: &str, mut reader: Pin<Box<dyn AsyncBufRead + Send>>) {
         let path = self.log_file_path(name);
-        let handle = tokio::spawn(async move {
+        tokio::spawn(async move {
             let mut file = tokio::fs::File::create(path).await?;
             tokio::io::copy(&mut reader, &mut file).await?;
             Ok::<_, std::io::Error>(())
@@ -98,6 +180,7 @@ impl Context {
     pub fn default_log_file_path(&self) -> PathBuf {
         let mut dir = self.log_dir();
         dir.push(format!("{}.log", self.clean_component_name()));
+        self.log_files.register(&self.component_name, &dir);
         dir
     }
 
@@ -108,23 +191,21 @@ impl Context {
     pub fn log_file_path(&self, log_name: &str) -> PathBuf {
         let mut dir = self.log_dir();
         dir.push(format!("{}.{}.log", self.clean_component_name(), log_name));
+        self.log_files.register(&self.component_name, &dir);
         dir
     }
 
+    /// Log files handed out to this component so far
+    pub(crate) fn log_files(&self) -> Vec<PathBuf> {
+        self.log_files.of(&self.component_name)
+    }
+
     /// Name of a binary file in the workspace
     pub fn workspace_binary_path(&self, binary_name: &str) -> PathBuf {
-        let profile = if cfg!(debug_assertions) {
-            "debug"
-        } else {
-            "release"
-        };
-        let mut path = self.workspace_root_dir.to_path_buf();
-        path.push("target");
-        path.push(profile);
-        path.push(binary_name);
-        path
+        workspace_binary_path(&self.workspace_root_dir, binary_name)
     }
 
+    #[allow(clippy::result_unit_err)]
     pub fn get_param(&self, key: &str) -> Result<Param, ()> {
         self.params.get(key).ok_or(()).map(|p| p.clone())
     }
@@ -136,4 +217,102 @@ impl Context {
         };
         self.params.insert(key.to_owned(), param.clone());
     }
+
+    /// Resource provided by another (already completed) set up
+    pub fn resource<R: Any + Send + Sync>(&self, component: &str) -> Result<Arc<R>, ResourceError> {
+        self.resources.get(component)
+    }
+
+    /// Register cleanup for something the set up has started so far.
+    ///
+    /// Registered tear downs run in the tear down phase even when the set up
+    /// fails, panics or times out afterwards. They run in reverse order of
+    /// registration, after the tear down returned by the set up (if any).
+    pub fn on_tear_down(&self, tear_down: impl TearDown + 'static) {
+        self.tear_downs.lock().unwrap().push(Box::new(tear_down));
+    }
+
+    /// Tear downs registered with [`Context::on_tear_down`]
+    pub(crate) fn take_tear_downs(&self) -> Vec<Box<dyn TearDown>> {
+        std::mem::take(&mut *self.tear_downs.lock().unwrap())
+    }
+
+    /// Forget the params and resource of a failed attempt, so a retry starts
+    /// without them. Tear downs it registered are kept, they still have to run.
+    pub(crate) fn discard_attempt(&self) {
+        let prefix = format!("{}.", self.clean_component_name());
+        self.params.retain(|key, _| !key.starts_with(&prefix));
+        self.resources.remove(&self.component_name);
+    }
+
+    /// Store the resource returned by this set up, used by `#[set_up]`
+    #[doc(hidden)]
+    pub fn provide<R: Any + Send + Sync>(&self, provides: Provides<R>) -> Option<Box<dyn TearDown>> {
+        self.resources.provide(&self.component_name, provides)
+    }
+}
+
+/// Read-only view of the environment passed to `#[itest]` functions.
+///
+/// Gives tests access to the params published by set ups and to the
+/// workspace paths, but does not allow new params to be set.
+pub struct TestContext {
+    params: Arc<DashMap<String, Param>>,
+    resources: Resources,
+    log_files: LogFiles,
+    workspace_root_dir: PathBuf,
+    test_name: String,
+}
+
+impl TestContext {
+    fn log_dir(&self) -> PathBuf {
+        let mut dir = log_dir(&self.workspace_root_dir);
+        dir.push("tests");
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Name of the test this context was created for
+    pub fn test_name(&self) -> &str {
+        &self.test_name
+    }
+
+    /// Root directory of the workspace
+    pub fn workspace_root_dir(&self) -> &Path {
+        &self.workspace_root_dir
+    }
+
+    /// Create a path suitable for logging the tests output
+    pub fn default_log_file_path(&self) -> PathBuf {
+        let mut dir = self.log_dir();
+        dir.push(format!("{}.log", clean_name(&self.test_name)));
+        self.log_files.register(&self.test_name, &dir);
+        dir
+    }
+
+    /// Create a path suitable for logging the tests output
+    ///
+    /// If your test only generates out output you should use
+    /// ```default_log_file_path()``` instead.
+    pub fn log_file_path(&self, log_name: &str) -> PathBuf {
+        let mut dir = self.log_dir();
+        dir.push(format!("{}.{}.log", clean_name(&self.test_name), log_name));
+        self.log_files.register(&self.test_name, &dir);
+        dir
+    }
+
+    /// Name of a binary file in the workspace
+    pub fn workspace_binary_path(&self, binary_name: &str) -> PathBuf {
+        workspace_binary_path(&self.workspace_root_dir, binary_name)
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn get_param(&self, key: &str) -> Result<Param, ()> {
+        self.params.get(key).ok_or(()).map(|p| p.clone())
+    }
+
+    /// Resource provided by a set up
+    pub fn resource<R: Any + Send + Sync>(&self, component: &str) -> Result<Arc<R>, ResourceError> {
+        self.resources.get(component)
+    }
 }
@@ -1,7 +1,7 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{self, Debug, Display};
 
-use crate::tasklist::TaskList;
+use crate::tasklist::{Edge, TaskList};
 
 #[allow(dead_code)]
 #[derive(Debug, PartialEq)]
@@ -17,6 +17,12 @@ pub enum Error {
         name: String,
         decls: Vec<String>,
     },
+
+    // Error when declarations (transitively) depend on themselves, each
+    // (name, decl) in the cycle depends on the next and the last on the first
+    DependencyCycle {
+        cycle: Vec<(String, String)>,
+    },
 }
 
 impl Display for Error {
@@ -40,10 +46,35 @@ impl Display for Error {
                 }
                 Ok(())
             }
+            Error::DependencyCycle { cycle } => {
+                let names: Vec<&str> = cycle.iter().map(|(name, _)| name.as_str()).collect();
+                writeln!(
+                    f,
+                    "Dependency cycle between components {} -> {}",
+                    names.join(" -> "),
+                    names[0]
+                )?;
+                for (name, decl) in cycle {
+                    writeln!(f, "\t#[set_up({})] at {}", name, decl)?;
+                }
+                Ok(())
+            }
         }
     }
 }
 
+/// How a declaration depends on another
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum DepKind {
+    /// `#[depends_on(X)]`, X must be declared
+    Required,
+    /// `#[depends_on(X, optional)]`, same as required if X is declared and
+    /// ignored otherwise
+    Optional,
+    /// `#[after(X)]`, only orders after X and does not require it to succeed
+    After,
+}
+
 struct Decl<D> {
     pub name: String,
     decl: D,
@@ -52,12 +83,12 @@ struct Decl<D> {
 #[derive(Default)]
 struct UnresolvedUsage {
     is_declared_by: Vec<usize>,
-    is_depended_on_by: Vec<usize>,
+    is_depended_on_by: Vec<(usize, DepKind)>,
 }
 
 struct ResolvedUsage {
     decl: usize,
-    is_dependend_on_by: Vec<usize>,
+    is_dependend_on_by: Vec<(usize, Edge)>,
 }
 
 pub struct Builder<D> {
@@ -72,7 +103,14 @@ impl<D: Display> Builder<D> {
             usages: BTreeMap::new(),
         }
     }
+    /// Declare a node that requires all of its deps
+    #[cfg(test)]
     pub fn declare_node(&mut self, decl: D, name: &str, deps: &[&str]) {
+        let deps: Vec<(&str, DepKind)> = deps.iter().map(|d| (*d, DepKind::Required)).collect();
+        self.declare_node_with_kinds(decl, name, &deps)
+    }
+
+    pub fn declare_node_with_kinds(&mut self, decl: D, name: &str, deps: &[(&str, DepKind)]) {
         let decl_idx = self.decls.len();
         self.decls.push(Decl {
             name: name.to_owned(),
@@ -80,8 +118,8 @@ impl<D: Display> Builder<D> {
         });
 
         self.usage(name).is_declared_by.push(decl_idx);
-        for d in deps {
-            self.usage(d).is_depended_on_by.push(decl_idx);
+        for (d, kind) in deps {
+            self.usage(d).is_depended_on_by.push((decl_idx, *kind));
         }
     }
 
@@ -93,13 +131,70 @@ impl<D: Display> Builder<D> {
         let usages = resolve_usages(&self.decls, self.usages)?;
         // if every usage resolved - we should have exactly one usage per decl
         assert_eq!(usages.len(), self.decls.len());
-        Ok(DepTable {
+        let table = DepTable {
             decls: self.decls,
             usages,
-        })
+        };
+
+        let cycles = find_cycles(&table.edges());
+        if cycles.is_empty() {
+            Ok(table)
+        } else {
+            Err(cycles
+                .into_iter()
+                .map(|cycle| Error::DependencyCycle {
+                    cycle: cycle
+                        .into_iter()
+                        .map(|i| (table.decls[i].name.to_owned(), table.decls[i].decl.to_string()))
+                        .collect(),
+                })
+                .collect())
+        }
     }
 }
 
+/// Depth first search for cycles, one per back edge found
+fn find_cycles(deps: &[Vec<(usize, Edge)>]) -> Vec<Vec<usize>> {
+    #[derive(Clone, Copy, PartialEq)]
+    enum Visit {
+        New,
+        OnPath,
+        Done,
+    }
+
+    fn visit(
+        id: usize,
+        deps: &[Vec<(usize, Edge)>],
+        state: &mut [Visit],
+        path: &mut Vec<usize>,
+        cycles: &mut Vec<Vec<usize>>,
+    ) {
+        state[id] = Visit::OnPath;
+        path.push(id);
+        for &(dep, _) in &deps[id] {
+            match state[dep] {
+                Visit::New => visit(dep, deps, state, path, cycles),
+                Visit::OnPath => {
+                    let start = path.iter().position(|&p| p == dep).unwrap();
+                    cycles.push(path[start..].to_vec());
+                }
+                Visit::Done => {}
+            }
+        }
+        path.pop();
+        state[id] = Visit::Done;
+    }
+
+    let mut state = vec![Visit::New; deps.len()];
+    let mut cycles = Vec::new();
+    for id in 0..deps.len() {
+        if state[id] == Visit::New {
+            visit(id, deps, &mut state, &mut Vec::new(), &mut cycles);
+        }
+    }
+    cycles
+}
+
 fn resolve_usages<D: Display>(
     decls: &[Decl<D>],
     usages: BTreeMap<String, UnresolvedUsage>,
@@ -108,7 +203,8 @@ fn resolve_usages<D: Display>(
     let mut resolved = Vec::new();
     for (name, usage) in usages {
         match resolve_usage(decls, name, usage) {
-            Ok(r) => resolved.push(r),
+            Ok(Some(r)) => resolved.push(r),
+            Ok(None) => {}
             Err(e) => errs.push(e),
         }
     }
@@ -123,7 +219,7 @@ fn resolve_usage<D: Display>(
     decls: &[Decl<D>],
     name: String,
     usage: UnresolvedUsage,
-) -> Result<ResolvedUsage, Error> {
+) -> Result<Option<ResolvedUsage>, Error> {
     if usage.is_declared_by.len() > 1 {
         Err(Error::DeclNameConflict {
             name,
@@ -134,19 +230,33 @@ fn resolve_usage<D: Display>(
                 .collect(),
         })
     } else if usage.is_declared_by.is_empty() {
-        Err(Error::UndeclaredDependency {
-            dep_name: name,
-            decls: usage
+        // optional dependencies on something undeclared are dropped
+        let required: Vec<(String, String)> = usage
+            .is_depended_on_by
+            .into_iter()
+            .filter(|(_, kind)| *kind != DepKind::Optional)
+            .map(|(i, _)| (decls[i].name.to_owned(), decls[i].decl.to_string()))
+            .collect();
+        if required.is_empty() {
+            Ok(None)
+        } else {
+            Err(Error::UndeclaredDependency {
+                dep_name: name,
+                decls: required,
+            })
+        }
+    } else {
+        Ok(Some(ResolvedUsage {
+            decl: usage.is_declared_by[0], // we know there will be exactly one,
+            is_dependend_on_by: usage
                 .is_depended_on_by
                 .into_iter()
-                .map(|i| (decls[i].name.to_owned(), decls[i].decl.to_string()))
+                .map(|(i, kind)| match kind {
+                    DepKind::Required | DepKind::Optional => (i, Edge::Requires),
+                    DepKind::After => (i, Edge::After),
+                })
                 .collect(),
-        })
-    } else {
-        Ok(ResolvedUsage {
-            decl: usage.is_declared_by[0], // we know there will be exactly one,
-            is_dependend_on_by: usage.is_depended_on_by,
-        })
+        }))
     }
 }
 
@@ -164,23 +274,78 @@ impl<D: Display> DepTable<D> {
         &self.decls[id].decl
     }
 
-    pub fn make_task_list(&self) -> TaskList {
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.decls.iter().position(|d| d.name == name)
+    }
+
+    /// Node ids ordered by name
+    pub fn sorted_by_name(&self) -> Vec<usize> {
+        let mut ids: Vec<usize> = (0..self.decls.len()).collect();
+        ids.sort_by(|a, b| self.name(*a).cmp(self.name(*b)));
+        ids
+    }
+
+    /// Direct dependencies of every node, indexed by node id
+    pub fn edges(&self) -> Vec<Vec<(usize, Edge)>> {
         let mut deps = Vec::with_capacity(self.usages.len());
         for _ in &self.usages {
             deps.push(vec![])
         }
         for usage in &self.usages {
-            for unblocked in &usage.is_dependend_on_by {
-                deps[*unblocked].push(usage.decl);
+            for (unblocked, edge) in &usage.is_dependend_on_by {
+                deps[*unblocked].push((usage.decl, *edge));
             }
         }
-        TaskList::new(&deps)
+        deps
+    }
+
+    /// Direct dependencies every node requires, ignoring ordering only ones
+    pub fn dep_lists(&self) -> Vec<Vec<usize>> {
+        self.edges()
+            .into_iter()
+            .map(|deps| {
+                deps.into_iter()
+                    .filter(|(_, edge)| *edge == Edge::Requires)
+                    .map(|(dep, _)| dep)
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// The given nodes plus everything they transitively depend on
+    pub fn dependency_closure(&self, roots: &[usize]) -> BTreeSet<usize> {
+        let deps = self.dep_lists();
+        let mut closure = BTreeSet::new();
+        let mut pending = roots.to_vec();
+        while let Some(id) = pending.pop() {
+            if closure.insert(id) {
+                pending.extend_from_slice(&deps[id]);
+            }
+        }
+        closure
+    }
+
+    pub fn make_task_list(&self) -> TaskList {
+        TaskList::from_edges(&self.edges())
+    }
+
+    /// Task list where every node waits for the nodes depending on it, as
+    /// used for tearing down. Failures are never propagated.
+    pub fn make_reverse_task_list(&self) -> TaskList {
+        let mut dependents = vec![Vec::new(); self.decls.len()];
+        for (id, deps) in self.edges().into_iter().enumerate() {
+            for (dep, _) in deps {
+                dependents[dep].push((id, Edge::After));
+            }
+        }
+        TaskList::from_edges(&dependents)
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::tasklist::{Status, Task};
 
     #[test]
     fn detect_undeclared_deps_and_name_conflicts() {
@@ -250,4 +415,100 @@ mod test {
             str
         )
     }
+
+    #[test]
+    fn detect_dependency_cycles() {
+        let mut bld = Builder::new();
+        bld.declare_node("1".to_owned(), "a", &["c"]);
+        bld.declare_node("2".to_owned(), "b", &["a"]);
+        bld.declare_node("3".to_owned(), "c", &["b"]);
+        bld.declare_node("4".to_owned(), "d", &["a"]);
+
+        let errs = bld.build().err().unwrap();
+        assert_eq!(
+            vec![Error::DependencyCycle {
+                cycle: vec![
+                    ("a".to_owned(), "1".to_owned()),
+                    ("c".to_owned(), "3".to_owned()),
+                    ("b".to_owned(), "2".to_owned()),
+                ]
+            }],
+            errs
+        );
+        assert_eq!(
+            "Dependency cycle between components a -> c -> b -> a\n\t#[set_up(a)] at 1\n\t#[set_up(c)] at 3\n\t#[set_up(b)] at 2\n",
+            errs[0].to_string()
+        );
+    }
+
+    #[test]
+    fn optional_deps_are_dropped_when_undeclared() {
+        let mut bld = Builder::new();
+        bld.declare_node("1".to_owned(), "a", &[]);
+        bld.declare_node_with_kinds(
+            "2".to_owned(),
+            "b",
+            &[("a", DepKind::Optional), ("x", DepKind::Optional)],
+        );
+        bld.declare_node_with_kinds("3".to_owned(), "c", &[("a", DepKind::After), ("x", DepKind::After)]);
+
+        let errs = bld.build().err().unwrap();
+        assert_eq!(
+            vec![Error::UndeclaredDependency {
+                dep_name: "x".to_owned(),
+                decls: vec![("c".to_owned(), "3".to_owned())],
+            }],
+            errs
+        );
+
+        let mut bld = Builder::new();
+        bld.declare_node("1".to_owned(), "a", &[]);
+        bld.declare_node_with_kinds(
+            "2".to_owned(),
+            "b",
+            &[("a", DepKind::Optional), ("x", DepKind::Optional)],
+        );
+        bld.declare_node_with_kinds("3".to_owned(), "c", &[("a", DepKind::After)]);
+        let table = bld.build().unwrap();
+
+        assert_eq!(
+            vec![vec![], vec![(0, Edge::Requires)], vec![(0, Edge::After)]],
+            table.edges()
+        );
+        assert_eq!(vec![vec![], vec![0], vec![]], table.dep_lists());
+    }
+
+    #[test]
+    fn reverse_task_list_starts_with_the_dependents() {
+        let mut bld = Builder::new();
+        bld.declare_node("1".to_owned(), "postgres", &[]);
+        bld.declare_node("2".to_owned(), "server", &["postgres"]);
+        bld.declare_node("3".to_owned(), "redis", &[]);
+        let table = bld.build().unwrap();
+
+        let mut tasks = table.make_reverse_task_list();
+        assert_eq!(Some(vec![Task(1), Task(2)]), tasks.pop_ready());
+        tasks.set_status(Task(1), Status::Success);
+        assert_eq!(Some(vec![Task(0)]), tasks.pop_ready());
+    }
+
+    #[test]
+    fn dependency_closure_includes_transitive_deps_only() {
+        let mut bld = Builder::new();
+        bld.declare_node("1".to_owned(), "a", &[]);
+        bld.declare_node("2".to_owned(), "b", &["a"]);
+        bld.declare_node("3".to_owned(), "c", &["b"]);
+        bld.declare_node("4".to_owned(), "d", &[]);
+        bld.declare_node("5".to_owned(), "e", &["d"]);
+        let table = bld.build().unwrap();
+
+        let c = table.find("c").unwrap();
+        let closure: Vec<&str> = table
+            .dependency_closure(&[c])
+            .into_iter()
+            .map(|id| table.name(id))
+            .collect();
+
+        assert_eq!(vec!["a", "b", "c"], closure);
+    }
 }
@@ -1,7 +1,9 @@
-use std::fmt;
+use std::{collections::BTreeSet, fmt, time::Duration};
+
+use libtest_mimic::Arguments;
 
 use crate::{
-    deptable::{Builder, DepTable}, tasklist::{Status, Task, TaskList}, RegisteredITest, RegisteredSetUp, SetUpFn, TestFn
+    budget::Cost, retry::Retry, deptable::{Builder, DepKind, DepTable}, tasklist::{Status, Task, TaskList}, RegisteredITest, RegisteredSetUp, SetUpFn, ShouldPanic, TestFn
 };
 
 pub struct SetUps {
@@ -20,10 +22,74 @@ impl SetUps {
             .iter()
             .map(|task| (*task, self.dep_table.name(task.0)))
     }
+
+    /// Set up names in an order they can be torn down in, dependents first
+    pub fn tear_down_order(&self) -> Vec<&str> {
+        let mut tasks = self.dep_table.make_reverse_task_list();
+        let mut order = Vec::new();
+        while let Some(ready) = tasks.pop_ready() {
+            for task in ready {
+                tasks.set_status(task, Status::Success);
+                order.push(self.dep_table.name(task.0));
+            }
+        }
+        order
+    }
+
+    /// Set ups the test (transitively) depends on.
+    ///
+    /// A test that does not declare any dependencies needs every set up.
+    pub fn required_by(&self, test: &TestDecl) -> BTreeSet<usize> {
+        if test.deps.is_empty() {
+            return self.display_order.iter().map(|t| t.0).collect();
+        }
+
+        let roots: Vec<usize> = test
+            .deps
+            .iter()
+            .filter_map(|dep| self.dep_table.find(dep))
+            .collect();
+
+        self.dep_table.dependency_closure(&roots)
+    }
+
+    /// Restrict the set ups to the ones the given tests (transitively) depend on.
+    ///
+    /// A test that does not declare any dependencies is assumed to need every
+    /// set up, so no pruning happens if one is selected.
+    pub fn prune_for(self, tests: &[&TestDecl]) -> Result<SetUps, ()> {
+        if tests.iter().any(|t| t.deps.is_empty()) {
+            return Ok(self);
+        }
+
+        let roots: Vec<usize> = tests
+            .iter()
+            .flat_map(|t| t.deps.iter())
+            .filter_map(|dep| self.dep_table.find(dep))
+            .collect();
+
+        let required: BTreeSet<&str> = self
+            .dep_table
+            .dependency_closure(&roots)
+            .into_iter()
+            .map(|id| self.dep_table.name(id))
+            .collect();
+
+        let dep_table = build_dep_table(|name| required.contains(name))?;
+        let display_order = dry_run_tasks(&dep_table);
+        Ok(SetUps {
+            display_order,
+            dep_table,
+        })
+    }
 }
 
 pub struct SetUpDecl {
     pub set_up_fn: &'static SetUpFn,
+    pub cost: Cost,
+    pub exclusive: &'static [&'static str],
+    pub timeout: Option<Duration>,
+    pub retry: Retry,
     file: String,
     line: usize,
 }
@@ -38,24 +104,97 @@ pub struct Tests {
     pub tests: Vec<TestDecl>
 }
 
+impl Tests {
+    /// Tests that will be run by libtest with the given arguments
+    pub fn selected(&self, args: &Arguments) -> Vec<&TestDecl> {
+        self.tests
+            .iter()
+            .filter(|t| !is_filtered_out(args, &t.name) && will_run(args, t))
+            .collect()
+    }
+
+    /// Tests that match the filter but will be ignored by libtest
+    pub fn ignored(&self, args: &Arguments) -> Vec<&TestDecl> {
+        self.tests
+            .iter()
+            .filter(|t| !is_filtered_out(args, &t.name) && !will_run(args, t))
+            .collect()
+    }
+}
+
+/// Mirrors how libtest treats `#[itest(ignore)]` and `--ignored`.
+fn will_run(args: &Arguments, test: &TestDecl) -> bool {
+    if args.ignored {
+        test.ignore.is_some()
+    } else {
+        test.ignore.is_none() || args.include_ignored
+    }
+}
+
+/// Mirrors the name filtering libtest applies before running trials.
+fn is_filtered_out(args: &Arguments, name: &str) -> bool {
+    if let Some(filter) = &args.filter {
+        let matches = if args.exact {
+            name == filter
+        } else {
+            name.contains(filter.as_str())
+        };
+        if !matches {
+            return true;
+        }
+    }
+
+    args.skip.iter().any(|skip| {
+        if args.exact {
+            name == skip
+        } else {
+            name.contains(skip.as_str())
+        }
+    })
+}
+
+#[allow(dead_code)]
 pub struct TestDecl {
     pub test_fn: &'static TestFn,
     pub name: String,
+    pub deps: Vec<String>,
+    pub ignore: Option<String>,
+    pub should_panic: ShouldPanic,
+    pub timeout: Option<Duration>,
     pub file: String,
     pub line: usize,
 }
 
-fn build_dep_table() -> Result<DepTable<SetUpDecl>, ()> {
+fn build_dep_table(include: impl Fn(&str) -> bool) -> Result<DepTable<SetUpDecl>, ()> {
     let mut bld = Builder::new();
 
-    for set_up in inventory::iter::<RegisteredSetUp> {
+    for set_up in inventory::iter::<RegisteredSetUp>.into_iter().filter(|s| include(s.name)) {
         let decl = SetUpDecl {
             set_up_fn: &set_up.set_up_fn,
+            cost: set_up.cost,
+            exclusive: set_up.exclusive,
+            timeout: set_up.timeout,
+            retry: set_up.retry,
             file: set_up.file.to_owned(),
             line: set_up.line,
         };
 
-        bld.declare_node(decl, set_up.name, set_up.deps);
+        // ordering only deps on set ups that were pruned away are dropped
+        let deps: Vec<(&str, DepKind)> = set_up
+            .deps
+            .iter()
+            .map(|dep| (*dep, DepKind::Required))
+            .chain(set_up.optional_deps.iter().map(|dep| (*dep, DepKind::Optional)))
+            .chain(
+                set_up
+                    .after
+                    .iter()
+                    .filter(|dep| include(dep) || !is_registered(dep))
+                    .map(|dep| (*dep, DepKind::After)),
+            )
+            .collect();
+
+        bld.declare_node_with_kinds(decl, set_up.name, &deps);
     }
 
     match bld.build() {
@@ -69,7 +208,11 @@ fn build_dep_table() -> Result<DepTable<SetUpDecl>, ()> {
     }
 }
 
-fn dry_run_tasks(dep_table: &DepTable<SetUpDecl>) -> Result<Vec<Task>, ()> {
+fn is_registered(name: &str) -> bool {
+    inventory::iter::<RegisteredSetUp>.into_iter().any(|s| s.name == name)
+}
+
+fn dry_run_tasks(dep_table: &DepTable<SetUpDecl>) -> Vec<Task> {
     let mut task = dep_table.make_task_list();
     let mut dry_run_order = Vec::new();
 
@@ -84,17 +227,14 @@ fn dry_run_tasks(dep_table: &DepTable<SetUpDecl>) -> Result<Vec<Task>, ()> {
         }
     }
 
-    if task.all_success() {
-        Ok(dry_run_order)
-    } else {
-        // could not find valid order
-        Err(())
-    }
+    // cycles are rejected when the dep table is built
+    assert!(task.all_success(), "could not find valid order");
+    dry_run_order
 }
 
 pub fn discover_setups() -> Result<SetUps, ()> {
-    let dep_table = build_dep_table()?;
-    let display_order = dry_run_tasks(&dep_table)?;
+    let dep_table = build_dep_table(|_| true)?;
+    let display_order = dry_run_tasks(&dep_table);
     Ok(SetUps {
         display_order,
         dep_table,
@@ -102,19 +242,55 @@ pub fn discover_setups() -> Result<SetUps, ()> {
 }
 
 
-pub fn discover_tests() -> Result<Tests, ()> {
+pub fn discover_tests(set_ups: &SetUps) -> Result<Tests, ()> {
     let mut tests = Vec::new();
+    let mut valid = true;
 
     for test in inventory::iter::<RegisteredITest> {
+        for dep in test.deps {
+            if set_ups.dep_table.find(dep).is_none() {
+                eprintln!("Undeclared component used in #[depends_on({})]", dep);
+                eprintln!("\tused by #[itest] {} at {}:{}", test.name, test.file, test.line);
+                valid = false;
+            }
+        }
+
         tests.push(
             TestDecl { 
                 name: test.name.to_owned(),
                 test_fn: &test.test_fn,
+                deps: test.deps.iter().map(|d| d.to_string()).collect(),
+                ignore: test.ignore.map(|r| r.to_owned()),
+                should_panic: test.should_panic,
+                timeout: test.timeout,
                 file: test.file.to_owned(),
                 line: test.line,
             }
         );
     }
 
-    Ok(Tests {tests})
-}
\ No newline at end of file
+    if valid {
+        Ok(Tests {tests})
+    } else {
+        Err(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(argv: &[&str]) -> Arguments {
+        Arguments::from_iter(std::iter::once("itest").chain(argv.iter().copied()))
+    }
+
+    #[test]
+    fn filter_matches_like_libtest() {
+        assert!(!is_filtered_out(&args(&[]), "can_call_server"));
+        assert!(!is_filtered_out(&args(&["call"]), "can_call_server"));
+        assert!(is_filtered_out(&args(&["envoy"]), "can_call_server"));
+        assert!(is_filtered_out(&args(&["--exact", "call"]), "can_call_server"));
+        assert!(!is_filtered_out(&args(&["--exact", "can_call_server"]), "can_call_server"));
+        assert!(is_filtered_out(&args(&["--skip", "server"]), "can_call_server"));
+    }
+}
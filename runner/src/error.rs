@@ -0,0 +1,181 @@
+use std::{error::Error, fmt, time::Duration};
+
+/// Why a set up did not complete
+#[derive(Debug)]
+pub enum SetUpError {
+    /// The set up returned an error
+    Failed {
+        source: Box<dyn Error + Send + Sync>,
+    },
+    /// The set up panicked, the location and backtrace are known when the
+    /// panic happened while the runner was watching
+    Panicked {
+        message: String,
+        location: Option<String>,
+        backtrace: Option<String>,
+    },
+    /// The set up did not finish within its timeout, after the elapsed time
+    Timeout(Duration),
+    /// The set up did not run because a set up it depends on failed
+    DependencyFailed { dependency: String },
+    /// Every attempt of a set up with retries failed, oldest first
+    RetriesExhausted(Vec<SetUpError>),
+    /// The run was interrupted while the set up was running
+    Interrupted,
+    /// Message only, kept for set ups that built errors themselves
+    #[deprecated(note = "return an error from the set up instead, it becomes `SetUpError::Failed`")]
+    Generic(String),
+}
+
+impl From<Box<dyn Error>> for SetUpError {
+    fn from(value: Box<dyn Error>) -> Self {
+        SetUpError::Failed {
+            source: Box::new(ErrorChain::capture(value.as_ref())),
+        }
+    }
+}
+
+impl fmt::Display for SetUpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SetUpError::Failed { source } => write!(f, "{}", source),
+            SetUpError::Panicked {
+                message,
+                location,
+                backtrace,
+            } => {
+                match location {
+                    Some(location) => write!(f, "panicked at {}: {}", location, message)?,
+                    None => write!(f, "panicked: {}", message)?,
+                }
+                if let Some(backtrace) = backtrace {
+                    write!(f, "\n{}", backtrace.trim_end())?;
+                }
+                Ok(())
+            }
+            SetUpError::Timeout(elapsed) => {
+                write!(f, "timed out after {:.02}s", elapsed.as_millis() as f64 / 1000.0)
+            }
+            SetUpError::DependencyFailed { dependency } => {
+                write!(f, "depends on failed set up {}", dependency)
+            }
+            SetUpError::RetriesExhausted(attempts) => {
+                write!(f, "failed after {} attempts", attempts.len())?;
+                for (i, err) in attempts.iter().enumerate() {
+                    write!(f, "\nattempt {}: {}", i + 1, Report(err))?;
+                }
+                Ok(())
+            }
+            SetUpError::Interrupted => f.write_str("interrupted"),
+            #[allow(deprecated)]
+            SetUpError::Generic(message) => f.write_str(message),
+        }
+    }
+}
+
+impl Error for SetUpError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SetUpError::Failed { source } => source.source(),
+            _ => None,
+        }
+    }
+}
+
+/// The error followed by its sources, one `caused by:` line each
+pub(crate) struct Report<'a>(pub &'a SetUpError);
+
+impl fmt::Display for Report<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)?;
+        let mut cause = self.0.source();
+        while let Some(err) = cause {
+            write!(f, "\ncaused by: {}", err)?;
+            cause = err.source();
+        }
+        Ok(())
+    }
+}
+
+/// Messages of an error and its sources.
+///
+/// The errors returned by set ups are not `Send`, so they can not be passed
+/// between workers as they are.
+#[derive(Debug)]
+struct ErrorChain {
+    message: String,
+    source: Option<Box<ErrorChain>>,
+}
+
+impl ErrorChain {
+    fn capture(err: &dyn Error) -> ErrorChain {
+        ErrorChain {
+            message: err.to_string(),
+            source: err.source().map(|s| Box::new(ErrorChain::capture(s))),
+        }
+    }
+}
+
+impl fmt::Display for ErrorChain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for ErrorChain {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source.as_deref().map(|s| s as &(dyn Error + 'static))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io;
+
+    #[derive(Debug)]
+    struct ConnectError(io::Error);
+
+    impl fmt::Display for ConnectError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("could not connect to postgres")
+        }
+    }
+
+    impl Error for ConnectError {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            Some(&self.0)
+        }
+    }
+
+    #[test]
+    fn failures_keep_the_source_chain() {
+        let err: Box<dyn Error> = Box::new(ConnectError(io::Error::other("connection refused")));
+        let err = SetUpError::from(err);
+
+        assert_eq!("could not connect to postgres", err.to_string());
+        assert_eq!("connection refused", err.source().unwrap().to_string());
+        assert!(err.source().unwrap().source().is_none());
+        assert_eq!(
+            "could not connect to postgres\ncaused by: connection refused",
+            Report(&err).to_string()
+        );
+    }
+
+    #[test]
+    fn retries_list_every_attempt() {
+        let err = SetUpError::RetriesExhausted(vec![
+            SetUpError::Timeout(Duration::from_millis(1500)),
+            SetUpError::Panicked {
+                message: "boom".to_owned(),
+                location: Some("src/db.rs:3:5".to_owned()),
+                backtrace: None,
+            },
+        ]);
+
+        assert_eq!(
+            "failed after 2 attempts\nattempt 1: timed out after 1.50s\nattempt 2: panicked at src/db.rs:3:5: boom",
+            err.to_string()
+        );
+    }
+}
@@ -0,0 +1,186 @@
+use std::{
+    fmt::{Display, Write},
+    str::FromStr,
+};
+
+use crate::{deptable::DepTable, tasklist::Edge};
+
+/// Output formats for `--graph`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GraphFormat {
+    Dot,
+    Mermaid,
+}
+
+impl FromStr for GraphFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dot" => Ok(GraphFormat::Dot),
+            "mermaid" => Ok(GraphFormat::Mermaid),
+            _ => Err(format!("unknown graph format `{}`, expected dot or mermaid", s)),
+        }
+    }
+}
+
+/// Render the set up dependency graph, edges point from a set up to the
+/// set ups it depends on. Ordering only (`#[after]`) edges are dashed.
+pub fn render<D: Display>(dep_table: &DepTable<D>, format: GraphFormat) -> String {
+    match format {
+        GraphFormat::Dot => render_dot(dep_table),
+        GraphFormat::Mermaid => render_mermaid(dep_table),
+    }
+}
+
+fn render_dot<D: Display>(dep_table: &DepTable<D>) -> String {
+    let deps = dep_table.edges();
+    let mut out = String::from("digraph set_ups {\n");
+    for id in dep_table.sorted_by_name() {
+        writeln!(
+            out,
+            "    \"{}\" [label=\"{}\\n{}\"];",
+            dep_table.name(id),
+            dep_table.name(id),
+            dep_table.decl(id)
+        )
+        .unwrap();
+    }
+    for id in dep_table.sorted_by_name() {
+        for (dep, edge) in sorted_deps(dep_table, &deps[id]) {
+            let style = match edge {
+                Edge::Requires => "",
+                Edge::After => " [style=dashed]",
+            };
+            writeln!(out, "    \"{}\" -> \"{}\"{};", dep_table.name(id), dep_table.name(dep), style).unwrap();
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+fn render_mermaid<D: Display>(dep_table: &DepTable<D>) -> String {
+    let deps = dep_table.edges();
+    let mut out = String::from("graph TD\n");
+    for id in dep_table.sorted_by_name() {
+        writeln!(
+            out,
+            "    {}[\"{}<br/>{}\"]",
+            dep_table.name(id),
+            dep_table.name(id),
+            dep_table.decl(id)
+        )
+        .unwrap();
+    }
+    for id in dep_table.sorted_by_name() {
+        for (dep, edge) in sorted_deps(dep_table, &deps[id]) {
+            let arrow = match edge {
+                Edge::Requires => "-->",
+                Edge::After => "-.->",
+            };
+            writeln!(out, "    {} {} {}", dep_table.name(id), arrow, dep_table.name(dep)).unwrap();
+        }
+    }
+    out
+}
+
+/// Every dependency chain starting at the named set up, as used by `--explain`
+pub fn explain<D: Display>(dep_table: &DepTable<D>, name: &str) -> Result<String, String> {
+    let id = dep_table
+        .find(name)
+        .ok_or_else(|| format!("No component declared with #[set_up({})]", name))?;
+
+    let deps = dep_table.edges();
+    let mut chains = Vec::new();
+    collect_chains(dep_table, &deps, &mut vec![(id, Edge::Requires)], &mut chains);
+
+    let mut out = format!("#[set_up({})] at {}\n", name, dep_table.decl(id));
+    if chains.is_empty() {
+        out.push_str("\thas no dependencies\n");
+    }
+    for chain in chains {
+        let mut line = dep_table.name(id).to_owned();
+        for (dep, edge) in &chain[1..] {
+            let arrow = match edge {
+                Edge::Requires => "->",
+                Edge::After => "-(after)->",
+            };
+            write!(line, " {} {}", arrow, dep_table.name(*dep)).unwrap();
+        }
+        writeln!(out, "\t{}", line).unwrap();
+    }
+    Ok(out)
+}
+
+fn collect_chains<D: Display>(
+    dep_table: &DepTable<D>,
+    deps: &[Vec<(usize, Edge)>],
+    chain: &mut Vec<(usize, Edge)>,
+    chains: &mut Vec<Vec<(usize, Edge)>>,
+) {
+    let (last, _) = *chain.last().unwrap();
+    if deps[last].is_empty() {
+        if chain.len() > 1 {
+            chains.push(chain.clone());
+        }
+        return;
+    }
+    for dep in sorted_deps(dep_table, &deps[last]) {
+        chain.push(dep);
+        collect_chains(dep_table, deps, chain, chains);
+        chain.pop();
+    }
+}
+
+fn sorted_deps<D: Display>(dep_table: &DepTable<D>, deps: &[(usize, Edge)]) -> Vec<(usize, Edge)> {
+    let mut deps = deps.to_vec();
+    deps.sort_by(|(a, _), (b, _)| dep_table.name(*a).cmp(dep_table.name(*b)));
+    deps
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::deptable::{Builder, DepKind};
+
+    fn dep_table() -> DepTable<String> {
+        let mut bld = Builder::new();
+        bld.declare_node("server.rs:3".to_owned(), "Server", &["Schema", "Postgres"]);
+        bld.declare_node("schema.rs:5".to_owned(), "Schema", &["Postgres"]);
+        bld.declare_node("postgres.rs:7".to_owned(), "Postgres", &[]);
+        bld.declare_node_with_kinds("tracing.rs:9".to_owned(), "Tracing", &[("Postgres", DepKind::After)]);
+        bld.build().unwrap()
+    }
+
+    #[test]
+    fn renders_dot_and_mermaid() {
+        assert_eq!(
+            "digraph set_ups {\n    \"Postgres\" [label=\"Postgres\\npostgres.rs:7\"];\n    \"Schema\" [label=\"Schema\\nschema.rs:5\"];\n    \"Server\" [label=\"Server\\nserver.rs:3\"];\n    \"Tracing\" [label=\"Tracing\\ntracing.rs:9\"];\n    \"Schema\" -> \"Postgres\";\n    \"Server\" -> \"Postgres\";\n    \"Server\" -> \"Schema\";\n    \"Tracing\" -> \"Postgres\" [style=dashed];\n}\n",
+            render(&dep_table(), GraphFormat::Dot)
+        );
+        assert_eq!(
+            "graph TD\n    Postgres[\"Postgres<br/>postgres.rs:7\"]\n    Schema[\"Schema<br/>schema.rs:5\"]\n    Server[\"Server<br/>server.rs:3\"]\n    Tracing[\"Tracing<br/>tracing.rs:9\"]\n    Schema --> Postgres\n    Server --> Postgres\n    Server --> Schema\n    Tracing -.-> Postgres\n",
+            render(&dep_table(), GraphFormat::Mermaid)
+        );
+    }
+
+    #[test]
+    fn explain_lists_every_chain() {
+        assert_eq!(
+            Ok("#[set_up(Server)] at server.rs:3\n\tServer -> Postgres\n\tServer -> Schema -> Postgres\n".to_owned()),
+            explain(&dep_table(), "Server")
+        );
+        assert_eq!(
+            Ok("#[set_up(Tracing)] at tracing.rs:9\n\tTracing -(after)-> Postgres\n".to_owned()),
+            explain(&dep_table(), "Tracing")
+        );
+        assert_eq!(
+            Ok("#[set_up(Postgres)] at postgres.rs:7\n\thas no dependencies\n".to_owned()),
+            explain(&dep_table(), "Postgres")
+        );
+        assert_eq!(
+            Err("No component declared with #[set_up(Redis)]".to_owned()),
+            explain(&dep_table(), "Redis")
+        );
+    }
+}
@@ -0,0 +1,93 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// How long each set up took the last time it succeeded.
+///
+/// Stored as `Name millis` lines in `target/itest/set_up_durations`, a
+/// missing or unreadable file just means there is no history yet.
+#[derive(Default)]
+pub(crate) struct Durations {
+    by_name: BTreeMap<String, Duration>,
+}
+
+fn durations_path(workspace_root_dir: &Path) -> PathBuf {
+    let mut path = workspace_root_dir.to_owned();
+    path.push("target");
+    path.push("itest");
+    path.push("set_up_durations");
+    path
+}
+
+impl Durations {
+    pub fn load(workspace_root_dir: &Path) -> Durations {
+        fs::read_to_string(durations_path(workspace_root_dir))
+            .map(|contents| Durations::parse(&contents))
+            .unwrap_or_default()
+    }
+
+    fn parse(contents: &str) -> Durations {
+        let by_name = contents
+            .lines()
+            .filter_map(|line| {
+                let (name, millis) = line.rsplit_once(' ')?;
+                Some((name.to_owned(), Duration::from_millis(millis.parse().ok()?)))
+            })
+            .collect();
+        Durations { by_name }
+    }
+
+    pub fn save(&self, workspace_root_dir: &Path) {
+        let contents: String = self
+            .by_name
+            .iter()
+            .map(|(name, duration)| format!("{} {}\n", name, duration.as_millis()))
+            .collect();
+        if let Err(e) = fs::write(durations_path(workspace_root_dir), contents) {
+            eprintln!("Failed to save set up durations: {}", e);
+        }
+    }
+
+    pub fn record(&mut self, name: &str, duration: Duration) {
+        self.by_name.insert(name.to_owned(), duration);
+    }
+
+    /// Scheduling weights in milliseconds, set ups that have not run before
+    /// are assumed to take as long as the average one.
+    pub fn weights<'a>(&self, names: impl Iterator<Item = &'a str>) -> Vec<u64> {
+        let known: Vec<u64> = self.by_name.values().map(|d| d.as_millis() as u64).collect();
+        let default = match known.len() {
+            0 => 1,
+            n => (known.iter().sum::<u64>() / n as u64).max(1),
+        };
+        names
+            .map(|name| {
+                self.by_name
+                    .get(name)
+                    .map_or(default, |d| (d.as_millis() as u64).max(1))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unknown_set_ups_get_the_average_weight() {
+        let durations = Durations::parse("Postgres 3000\nRedis 1000\nbad line\n");
+        assert_eq!(
+            vec![3000, 2000, 1000],
+            durations.weights(["Postgres", "Server", "Redis"].into_iter())
+        );
+    }
+
+    #[test]
+    fn without_history_every_set_up_weighs_the_same() {
+        assert_eq!(vec![1, 1], Durations::default().weights(["a", "b"].into_iter()));
+    }
+}
@@ -0,0 +1,111 @@
+use std::{
+    pin::pin,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use futures::future::{Either, select};
+use tokio::sync::Notify;
+
+/// Set once the run is interrupted with Ctrl-C or SIGTERM
+#[derive(Clone, Default)]
+pub(crate) struct Interrupt {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    interrupted: AtomicBool,
+    notify: Notify,
+}
+
+impl Interrupt {
+    /// Listen for Ctrl-C and SIGTERM, a second signal exits immediately
+    /// without tearing anything down
+    pub fn listen() -> Interrupt {
+        let interrupt = Interrupt::default();
+        let trigger = interrupt.clone();
+        tokio::spawn(async move {
+            wait_for_signal().await;
+            eprintln!("\ninterrupted, tearing down (interrupt again to exit immediately)");
+            trigger.trigger();
+
+            wait_for_signal().await;
+            eprintln!("\ninterrupted again, exiting without tearing down");
+            std::process::exit(130);
+        });
+        interrupt
+    }
+
+    pub fn trigger(&self) {
+        self.inner.interrupted.store(true, Ordering::SeqCst);
+        self.inner.notify.notify_waiters();
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.inner.interrupted.load(Ordering::SeqCst)
+    }
+
+    pub async fn wait(&self) {
+        let mut notified = pin!(self.inner.notify.notified());
+        // register before checking, so a trigger in between is not missed
+        notified.as_mut().enable();
+        if self.is_set() {
+            return;
+        }
+        notified.await;
+    }
+
+    /// Run the future unless the run is interrupted first
+    pub async fn run_until<F: Future>(&self, future: F) -> Option<F::Output> {
+        match select(pin!(future), pin!(self.wait())).await {
+            Either::Left((output, _)) => Some(output),
+            Either::Right(_) => None,
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    match signal(SignalKind::terminate()) {
+        Ok(mut terminate) => {
+            select(pin!(tokio::signal::ctrl_c()), pin!(terminate.recv())).await;
+        }
+        Err(_) => {
+            let _ = tokio::signal::ctrl_c().await;
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn interrupting_cancels_waiting_futures() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let interrupt = Interrupt::default();
+
+        assert_eq!(Some(1), rt.block_on(interrupt.run_until(async { 1 })));
+
+        let trigger = interrupt.clone();
+        let cancelled = rt.block_on(async {
+            tokio::spawn(async move { trigger.trigger() });
+            interrupt.run_until(std::future::pending::<()>()).await
+        });
+        assert_eq!(None, cancelled);
+        assert!(interrupt.is_set());
+    }
+}
@@ -0,0 +1,367 @@
+use std::{
+    collections::BTreeMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+/// Something a set up started that a later run can check on and tear down,
+/// as needed by `--keep-env`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeepHandle {
+    Process { pid: u32 },
+    /// A docker container, by id
+    Container { id: String },
+}
+
+impl KeepHandle {
+    fn stop(&self) -> bool {
+        match self {
+            KeepHandle::Process { pid } => succeeds(Command::new("kill").arg(pid.to_string())),
+            KeepHandle::Container { id } => succeeds(Command::new("docker").args(["rm", "--force", id])),
+        }
+    }
+}
+
+/// Start time of a running process in clock ticks after boot, from `/proc`.
+///
+/// `None` when the process is gone, a zombie or can not be inspected.
+fn process_start_time(pid: u32) -> Option<u64> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // the command name in parentheses may contain spaces
+    let (_, fields) = stat.rsplit_once(')')?;
+    let fields: Vec<&str> = fields.split_whitespace().collect();
+    if fields.first() == Some(&"Z") {
+        return None;
+    }
+    fields.get(19)?.parse().ok()
+}
+
+fn start_times_readable() -> bool {
+    process_start_time(std::process::id()).is_some()
+}
+
+/// A handle as stored in the state file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct KeptHandle {
+    pub handle: KeepHandle,
+    /// Start time of a process, so a reused pid is not mistaken for it
+    pub started: Option<u64>,
+}
+
+impl KeptHandle {
+    pub fn record(handle: KeepHandle) -> KeptHandle {
+        let started = match &handle {
+            KeepHandle::Process { pid } => process_start_time(*pid),
+            KeepHandle::Container { .. } => None,
+        };
+        KeptHandle { handle, started }
+    }
+
+    /// Whether it is still running, an error when that can not be checked.
+    ///
+    /// A process with a recorded start time only counts as alive when it
+    /// matches. Where start times can not be read, only the pid is checked.
+    fn is_alive(&self) -> Result<bool, String> {
+        let unknown = |e: io::Error| format!("Failed to check on {}: {}", self.handle, e);
+        match &self.handle {
+            KeepHandle::Process { pid } => match self.started {
+                Some(_) if start_times_readable() => Ok(process_start_time(*pid) == self.started),
+                _ => Command::new("kill")
+                    .args(["-0", &pid.to_string()])
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .status()
+                    .map(|status| status.success())
+                    .map_err(unknown),
+            },
+            KeepHandle::Container { id } => Command::new("docker")
+                .args(["inspect", "--format", "{{.State.Running}}", id])
+                .stderr(Stdio::null())
+                .output()
+                .map(|out| out.status.success() && out.stdout.trim_ascii() == b"true")
+                .map_err(unknown),
+        }
+    }
+
+    fn tear_down(&self) -> Result<(), String> {
+        // gone already, or its pid now belongs to something else
+        if !self.is_alive()? {
+            return Ok(());
+        }
+        if self.handle.stop() || !self.is_alive()? {
+            Ok(())
+        } else {
+            Err(format!("Failed to tear down {}", self.handle))
+        }
+    }
+}
+
+impl fmt::Display for KeptHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.handle)?;
+        if let Some(started) = self.started {
+            write!(f, " {}", started)?;
+        }
+        Ok(())
+    }
+}
+
+fn succeeds(command: &mut Command) -> bool {
+    command
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+impl fmt::Display for KeepHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeepHandle::Process { pid } => write!(f, "process {}", pid),
+            KeepHandle::Container { id } => write!(f, "container {}", id),
+        }
+    }
+}
+
+/// What a kept set up left running
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct KeptComponent {
+    pub params: Vec<(String, String)>,
+    pub handles: Vec<KeptHandle>,
+    /// The set up provided a resource, which a later run can not restore
+    pub resource: bool,
+}
+
+/// Environment left running by `--keep-env`, stored in `target/itest/kept_env`.
+///
+/// One `component Name` line per set up, followed by its `param key value`,
+/// `process pid start_time`, `container id` and `resource` lines.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct KeptEnv {
+    pub components: BTreeMap<String, KeptComponent>,
+}
+
+fn state_path(workspace_root_dir: &Path) -> PathBuf {
+    let mut path = workspace_root_dir.to_owned();
+    path.push("target");
+    path.push("itest");
+    path.push("kept_env");
+    path
+}
+
+impl KeptEnv {
+    /// The kept environment, if there is one
+    pub fn load(workspace_root_dir: &Path) -> Result<Option<KeptEnv>, String> {
+        let path = state_path(workspace_root_dir);
+        match fs::read_to_string(&path) {
+            Ok(contents) => KeptEnv::parse(&contents)
+                .map(Some)
+                .map_err(|e| format!("{}: {}", path.display(), e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    fn parse(contents: &str) -> Result<KeptEnv, String> {
+        let mut env = KeptEnv::default();
+        let mut current: Option<&mut KeptComponent> = None;
+        for (idx, line) in contents.lines().enumerate() {
+            let invalid = || format!("invalid line {}: {}", idx + 1, line);
+            if line == "resource" {
+                current.as_deref_mut().ok_or_else(invalid)?.resource = true;
+                continue;
+            }
+            let (kind, rest) = line.split_once(' ').ok_or_else(invalid)?;
+            if kind == "component" {
+                current = Some(env.components.entry(rest.to_owned()).or_default());
+                continue;
+            }
+
+            let component = current.as_deref_mut().ok_or_else(invalid)?;
+            match kind {
+                "param" => {
+                    let (key, value) = rest.split_once(' ').ok_or_else(invalid)?;
+                    component.params.push((key.to_owned(), unescape(value)));
+                }
+                "process" => {
+                    let (pid, started) = match rest.split_once(' ') {
+                        Some((pid, started)) => (pid, Some(started.parse().map_err(|_| invalid())?)),
+                        None => (rest, None),
+                    };
+                    component.handles.push(KeptHandle {
+                        handle: KeepHandle::Process {
+                            pid: pid.parse().map_err(|_| invalid())?,
+                        },
+                        started,
+                    })
+                }
+                "container" => component.handles.push(KeptHandle {
+                    handle: KeepHandle::Container { id: rest.to_owned() },
+                    started: None,
+                }),
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(env)
+    }
+
+    pub fn save(&self, workspace_root_dir: &Path) -> Result<(), String> {
+        let mut contents = String::new();
+        for (name, component) in &self.components {
+            contents.push_str(&format!("component {}\n", name));
+            for (key, value) in &component.params {
+                contents.push_str(&format!("param {} {}\n", key, escape(value)));
+            }
+            for handle in &component.handles {
+                contents.push_str(&format!("{}\n", handle));
+            }
+            if component.resource {
+                contents.push_str("resource\n");
+            }
+        }
+        let path = state_path(workspace_root_dir);
+        fs::write(&path, contents).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn remove(workspace_root_dir: &Path) {
+        let _ = fs::remove_file(state_path(workspace_root_dir));
+    }
+
+    /// Components that have something that is no longer running, or that
+    /// can not be checked on
+    pub fn stale(&self) -> Vec<&str> {
+        self.components
+            .iter()
+            .filter(|(_, c)| !c.handles.iter().all(|h| h.is_alive() == Ok(true)))
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+
+    /// Tear down everything that was kept, returns the failures.
+    ///
+    /// Components are torn down in the given order, those that are no longer
+    /// declared go last.
+    pub fn tear_down(&self, order: &[String]) -> Vec<String> {
+        let unknown = self.components.keys().filter(|name| !order.contains(name));
+        order
+            .iter()
+            .chain(unknown)
+            .filter_map(|name| self.components.get(name).map(|c| (name, c)))
+            .flat_map(|(name, c)| {
+                c.handles
+                    .iter()
+                    .filter_map(move |h| h.tear_down().err().map(|e| format!("{}: {}", name, e)))
+            })
+            .collect()
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape(value: &str) -> String {
+    let mut out = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                out.push('\n');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                out.push('\\');
+                chars.next();
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn state_survives_a_round_trip() {
+        let mut env = KeptEnv::default();
+        env.components.insert(
+            "Postgres".to_owned(),
+            KeptComponent {
+                params: vec![
+                    ("Postgres.url".to_owned(), "postgresql://localhost:15432/test_db".to_owned()),
+                    ("Postgres.banner".to_owned(), "two\nlines \\ slash".to_owned()),
+                ],
+                handles: vec![KeptHandle {
+                    handle: KeepHandle::Container { id: "3f2a".to_owned() },
+                    started: None,
+                }],
+                resource: true,
+            },
+        );
+        env.components.insert(
+            "Server".to_owned(),
+            KeptComponent {
+                params: vec![],
+                handles: vec![KeptHandle {
+                    handle: KeepHandle::Process { pid: 4242 },
+                    started: Some(1234567),
+                }],
+                resource: false,
+            },
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("target/itest")).unwrap();
+        env.save(dir.path()).unwrap();
+
+        assert_eq!(Some(env), KeptEnv::load(dir.path()).unwrap());
+        KeptEnv::remove(dir.path());
+        assert_eq!(None, KeptEnv::load(dir.path()).unwrap());
+    }
+
+    #[test]
+    fn malformed_state_is_reported() {
+        assert_eq!(
+            Err("invalid line 1: process 12".to_owned()),
+            KeptEnv::parse("process 12\n")
+        );
+        assert_eq!(
+            Err("invalid line 2: process twelve".to_owned()),
+            KeptEnv::parse("component Server\nprocess twelve\n")
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn processes_are_recognised_by_their_start_time() {
+        let pid = std::process::id();
+        let handle = KeptHandle::record(KeepHandle::Process { pid });
+        assert!(handle.started.is_some());
+        assert_eq!(Ok(true), handle.is_alive());
+
+        // same pid, but started at another time
+        let reused = KeptHandle {
+            started: handle.started.map(|started| started + 1),
+            ..handle
+        };
+        assert_eq!(Ok(false), reused.is_alive());
+        assert_eq!(Ok(()), reused.tear_down());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn processes_without_a_start_time_are_checked_by_pid() {
+        let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+        let handle = KeptHandle {
+            handle: KeepHandle::Process { pid: child.id() },
+            started: None,
+        };
+        assert_eq!(Ok(true), handle.is_alive());
+
+        assert_eq!(Ok(()), handle.tear_down());
+        assert!(!child.wait().unwrap().success());
+    }
+}
@@ -3,39 +3,49 @@
 
 use std::pin::Pin;
 use std::process::ExitCode;
-use std::{path::PathBuf, process::Command};
+use std::time::Duration;
+use std::{path::{Path, PathBuf}, process::Command};
 
 use async_trait::async_trait;
+use libtest_mimic::Arguments;
 pub use inventory::{collect, submit};
-pub use itest_macros::{depends_on, itest, set_up};
+pub use itest_macros::{after, depends_on, exclusive, itest, set_up};
 
 pub mod components;
 
+mod budget;
+mod cli;
 mod context;
 mod deptable;
 mod discover;
+mod error;
+mod graph;
+mod history;
+mod interrupt;
+mod keep;
+mod logs;
 mod phases;
 mod tasklist;
 
 mod progress;
+mod resource;
+mod retry;
 
 
 use crate::discover::{discover_setups, discover_tests, SetUps, Tests};
+use crate::interrupt::Interrupt;
+use crate::keep::KeptEnv;
 use crate::progress::{OverallResult, ProgressListener, ProgressMonitor};
 
 use tasklist::Task;
-pub use context::{Context, GlobalContext, Param};
-
-#[derive(Debug)]
-pub enum SetUpError {
-    Generic(String),
-}
-
-impl From<Box<dyn std::error::Error>> for SetUpError {
-    fn from(value: Box<dyn std::error::Error>) -> Self {
-        SetUpError::Generic(format!("{}", value))
-    }
-}
+pub use tasklist::FailureMode;
+pub use phases::SetUpConcurrency;
+pub use budget::{Budget, Cost};
+pub use context::{Context, GlobalContext, Param, TestContext};
+pub use resource::{Provides, ResourceError};
+pub use retry::{Backoff, Retry};
+pub use error::SetUpError;
+pub use keep::KeepHandle;
 
 pub type SetUpResult = Result<Option<Box<dyn TearDown>>, SetUpError>;
 
@@ -43,7 +53,19 @@ pub type SetFnOutput = Pin<Box<dyn Future<Output = SetUpResult> + Send + 'static
 
 pub type SetUpFn = fn(Context) -> SetFnOutput;
 
-pub type TestFn = fn();
+pub type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+pub type TestFnOutput = Pin<Box<dyn Future<Output = TestResult> + 'static>>;
+
+/// Test function registered by `#[itest]`.
+///
+/// Sync tests are run directly on the test thread, async tests are driven
+/// to completion on the runner's tokio runtime.
+#[derive(Copy, Clone)]
+pub enum TestFn {
+    Sync(fn(TestContext) -> TestResult),
+    Async(fn(TestContext) -> TestFnOutput),
+}
 
 inventory::collect!(RegisteredSetUp);
 
@@ -51,13 +73,36 @@ pub struct RegisteredSetUp {
     pub name: &'static str,
     pub set_up_fn: SetUpFn,
     pub deps: &'static [&'static str],
+    /// `#[depends_on(X, optional)]`, ignored when X is not registered
+    pub optional_deps: &'static [&'static str],
+    /// `#[after(X)]`, ordering only
+    pub after: &'static [&'static str],
+    pub cost: Cost,
+    /// `#[set_up(Name, timeout = "60s")]`, overrides [`ITest::with_set_up_timeout`]
+    pub timeout: Option<Duration>,
+    /// `#[set_up(Name, retries = 3)]`, a failed set up is run again with a fresh [`Context`]
+    pub retry: Retry,
+    /// `#[exclusive("lock")]`, only one set up holding a lock runs at a time
+    pub exclusive: &'static [&'static str],
     pub file: &'static str,
     pub line: usize,
 }
 
+/// Whether a test is expected to panic, as set by `#[itest(should_panic)]`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ShouldPanic {
+    No,
+    Yes,
+    YesWithMessage(&'static str),
+}
+
 pub struct RegisteredITest {
     pub name: &'static str,
     pub test_fn: TestFn,
+    pub deps: &'static [&'static str],
+    pub ignore: Option<&'static str>,
+    pub should_panic: ShouldPanic,
+    pub timeout: Option<Duration>,
     pub file: &'static str,
     pub line: usize,
 }
@@ -69,6 +114,13 @@ pub type TearDownResult =  Result<(), Box<dyn std::error::Error>>;
 #[async_trait]
 pub trait TearDown: Send {
     async fn tear_down(&mut self) -> TearDownResult;
+
+    /// What a later run needs to check on and tear down the component when
+    /// the environment is kept with `--keep-env`. `None` when it can not be
+    /// kept, it is then torn down at the end of the run as usual.
+    fn keep_handles(&self) -> Option<Vec<KeepHandle>> {
+        None
+    }
 }
 
 #[derive(Default)]
@@ -81,28 +133,139 @@ impl TearDowns {
         self.tear_downs.push((task, tear_down))
     }
 
+    /// Everything a component registered, torn down in reverse order
+    pub(crate) fn push_all(&mut self, task: Task, mut tear_downs: Vec<Box<dyn TearDown + 'static>>) {
+        match tear_downs.len() {
+            0 => {}
+            1 => self.push(task, tear_downs.pop().unwrap()),
+            _ => self.push(task, Box::new(TearDownStack(tear_downs))),
+        }
+    }
+
     pub fn len(&self) -> usize {
         self.tear_downs.len()
     }
 
-    pub fn pop(&mut self) -> Option<(Task,Box<dyn TearDown + 'static>)> {
-        self.tear_downs.pop()
+    pub fn is_empty(&self) -> bool {
+        self.tear_downs.is_empty()
+    }
+
+    pub fn remove(&mut self, task: Task) -> Option<Box<dyn TearDown + 'static>> {
+        let idx = self.tear_downs.iter().position(|(t, _)| *t == task)?;
+        Some(self.tear_downs.remove(idx).1)
     }
 }
 
 
-pub struct ITest {}
+/// Tear downs of a single component, the last one registered runs first.
+///
+/// All of them run even when one fails, the errors are combined.
+struct TearDownStack(Vec<Box<dyn TearDown + 'static>>);
+
+#[async_trait]
+impl TearDown for TearDownStack {
+    async fn tear_down(&mut self) -> TearDownResult {
+        let mut errs = Vec::new();
+        while let Some(mut tear_down) = self.0.pop() {
+            if let Err(e) = tear_down.tear_down().await {
+                errs.push(e.to_string());
+            }
+        }
+        if errs.is_empty() {
+            Ok(())
+        } else {
+            Err(errs.join("\n").into())
+        }
+    }
+
+    fn keep_handles(&self) -> Option<Vec<KeepHandle>> {
+        self.0
+            .iter()
+            .map(|tear_down| tear_down.keep_handles())
+            .collect::<Option<Vec<_>>>()
+            .map(|handles| handles.concat())
+    }
+}
+
+/// Environment variable overriding the set up concurrency
+const SET_UP_CONCURRENCY_ENV: &str = "ITEST_SET_UP_CONCURRENCY";
+
+/// Lines of each log shown with a failure, unless configured otherwise
+const DEFAULT_LOG_TAIL_LINES: usize = 20;
+
+#[derive(Default)]
+pub struct ITest {
+    failure_mode: FailureMode,
+    set_up_concurrency: SetUpConcurrency,
+    budget: Budget,
+    set_up_timeout: Option<Duration>,
+    log_tail_lines: Option<usize>,
+    keep_env: bool,
+    env_only: bool,
+}
 
 impl ITest {
     pub fn new() -> Self {
-        Self {}
+        Self::default()
+    }
+
+    /// Choose what happens to the rest of the run when a set up fails.
+    ///
+    /// With [`FailureMode::SkipDependents`] only the set ups and tests that
+    /// depend on the failed set up are skipped, everything else still runs.
+    pub fn with_failure_mode(self, failure_mode: FailureMode) -> ITest {
+        ITest {
+            failure_mode,
+            ..self
+        }
+    }
+
+    /// Choose how many set ups may run at the same time (3 by default).
+    ///
+    /// Can be overridden with the `--set-up-concurrency` flag or the
+    /// `ITEST_SET_UP_CONCURRENCY` environment variable, which accept a
+    /// number or `unbounded`. The flag takes precedence.
+    pub fn with_set_up_concurrency(self, set_up_concurrency: SetUpConcurrency) -> ITest {
+        ITest {
+            set_up_concurrency,
+            ..self
+        }
+    }
+
+    /// Limit the combined `cost(..)` of the set ups running at the same time.
+    ///
+    /// Unlimited by default.
+    pub fn with_budget(self, budget: Budget) -> ITest {
+        ITest { budget, ..self }
+    }
+
+    /// Default timeout for set ups that do not declare their own with
+    /// `#[set_up(Name, timeout = "60s")]`. No timeout by default.
+    pub fn with_set_up_timeout(self, timeout: Duration) -> ITest {
+        ITest {
+            set_up_timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// How many lines of a component's logs to print when its set up, or a
+    /// test depending on it, fails (20 by default, 0 turns this off).
+    pub fn with_log_tail_lines(self, lines: usize) -> ITest {
+        ITest {
+            log_tail_lines: Some(lines),
+            ..self
+        }
+    }
+
+    fn log_tail_lines(&self) -> usize {
+        self.log_tail_lines.unwrap_or(DEFAULT_LOG_TAIL_LINES)
     }
 }
 
 fn find_workspace_root_dir() -> PathBuf {
     // Get workspace root
     let output = Command::new("cargo")
-        .args(&["locate-project", "--workspace", "--message-format=plain"])
+        .args(["locate-project", "--workspace", "--message-format=plain"])
         .output()
         .expect("Failed to locate workspace");
 
@@ -126,28 +289,189 @@ impl ITest {
     }
 
     async fn run_async(self) -> OverallResult {
-        let set_ups = discover_setups().unwrap();
-        let tests = discover_tests().unwrap();
+        let (runner_args, libtest_args) = match cli::parse(std::env::args_os()) {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                return OverallResult::Failed;
+            }
+        };
+        let args = Arguments::from_iter(libtest_args);
+
+        let env_concurrency = match std::env::var(SET_UP_CONCURRENCY_ENV) {
+            Ok(value) => match value.parse() {
+                Ok(concurrency) => Some(concurrency),
+                Err(e) => {
+                    eprintln!("error: {}: {}", SET_UP_CONCURRENCY_ENV, e);
+                    return OverallResult::Failed;
+                }
+            },
+            Err(_) => None,
+        };
+        let this = match runner_args.set_up_concurrency.or(env_concurrency) {
+            Some(concurrency) => self.with_set_up_concurrency(concurrency),
+            None => self,
+        };
+        let this = ITest {
+            keep_env: runner_args.keep_env,
+            env_only: runner_args.env_only,
+            ..this
+        };
+
+        // discovery errors have already been reported
+        let Ok(set_ups) = discover_setups() else {
+            return OverallResult::Failed;
+        };
+
+        if let Some(format) = runner_args.graph {
+            print!("{}", graph::render(&set_ups.dep_table, format));
+            return OverallResult::Ok;
+        }
+
+        if let Some(name) = runner_args.explain {
+            return match graph::explain(&set_ups.dep_table, &name) {
+                Ok(explanation) => {
+                    print!("{}", explanation);
+                    OverallResult::Ok
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    OverallResult::Failed
+                }
+            };
+        }
+
+        // the kept environment may have set ups this run prunes, so they are
+        // torn down in the order of all of them
+        let workspace_root_dir = find_workspace_root_dir();
+        let tear_down_order: Vec<String> = set_ups.tear_down_order().into_iter().map(str::to_owned).collect();
+        if runner_args.tear_down_env {
+            return tear_down_kept_env(&workspace_root_dir, &tear_down_order);
+        }
+
+        let Ok(tests) = discover_tests(&set_ups) else {
+            return OverallResult::Failed;
+        };
+        // an env only run sets up everything, there are no tests to select
+        let set_ups = if this.env_only {
+            set_ups
+        } else {
+            let Ok(set_ups) = set_ups.prune_for(&tests.selected(&args)) else {
+                return OverallResult::Failed;
+            };
+            set_ups
+        };
+        let Ok(kept) = load_kept_env(&workspace_root_dir, &set_ups, &tear_down_order) else {
+            return OverallResult::Failed;
+        };
         let task_names = set_ups.tasks().map(|(t, n)| (t, n.to_string())).collect();
        
+        // from here on an interrupted run still tears down what was set up
+        let interrupt = Interrupt::listen();
         let monitor = ProgressMonitor::new(task_names);
         let progress = monitor.listener();
-        let result = self.run_with_monitor(set_ups, tests, &progress).await;
+        let result = this
+            .run_with_monitor(args, set_ups, tests, &workspace_root_dir, kept, &interrupt, &progress)
+            .await;
         monitor.shutdown().await;
 
         result
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn run_with_monitor(self, 
+        args: Arguments,
         set_ups: SetUps,
         tests: Tests,
+        workspace_root_dir: &Path,
+        kept: Option<KeptEnv>,
+        interrupt: &Interrupt,
         progress: &ProgressListener,
     ) -> OverallResult {
    
-        let workspace_root_dir = find_workspace_root_dir();
-     
-        let mut global_ctx = GlobalContext::new(&workspace_root_dir);
+        let mut global_ctx = GlobalContext::new(workspace_root_dir);
 
-        phases::run(&mut global_ctx,  set_ups, tests, progress).await
+        phases::run(&mut global_ctx, args, &self, set_ups, tests, kept, interrupt, progress).await
+    }
+}
+
+/// The environment kept by an earlier run, when it is still running and can
+/// be used for the set ups of this run. Otherwise it is torn down.
+fn load_kept_env(
+    workspace_root_dir: &Path,
+    set_ups: &SetUps,
+    tear_down_order: &[String],
+) -> Result<Option<KeptEnv>, ()> {
+    let kept = match KeptEnv::load(workspace_root_dir) {
+        Ok(Some(kept)) => kept,
+        Ok(None) => return Ok(None),
+        Err(e) => {
+            eprintln!("Failed to load the kept environment: {}", e);
+            return Err(());
+        }
+    };
+
+    let stale = kept.stale();
+    let reason = if !stale.is_empty() {
+        format!("kept environment is no longer running ({})", stale.join(", "))
+    } else if let Some((name, provider)) = needs_kept_resource(&kept, set_ups) {
+        format!(
+            "#[set_up({})] needs the resource of the kept #[set_up({})], which can not be reused",
+            name, provider
+        )
+    } else {
+        eprintln!("reusing the environment kept by an earlier run, --tear-down-env tears it down");
+        return Ok(Some(kept));
+    };
+
+    eprintln!("{}, setting up again", reason);
+    for e in kept.tear_down(tear_down_order) {
+        eprintln!("{}", e);
+    }
+    KeptEnv::remove(workspace_root_dir);
+    Ok(None)
+}
+
+/// A set up that is not kept, but depends on a kept set up that provided a
+/// resource. Resources do not outlive the run, so it could not get it.
+fn needs_kept_resource<'a>(kept: &KeptEnv, set_ups: &'a SetUps) -> Option<(&'a str, &'a str)> {
+    set_ups
+        .tasks()
+        .filter(|(_, name)| !kept.components.contains_key(*name))
+        .find_map(|(task, name)| {
+            set_ups
+                .dep_table
+                .dependency_closure(&[task.0])
+                .into_iter()
+                .map(|id| set_ups.dep_table.name(id))
+                .find(|dep| kept.components.get(*dep).is_some_and(|c| c.resource))
+                .map(|dep| (name, dep))
+        })
+}
+
+/// Tear down what an earlier `--keep-env` run left running
+fn tear_down_kept_env(workspace_root_dir: &Path, tear_down_order: &[String]) -> OverallResult {
+    let kept = match KeptEnv::load(workspace_root_dir) {
+        Ok(Some(kept)) => kept,
+        Ok(None) => {
+            eprintln!("no kept environment to tear down");
+            return OverallResult::Ok;
+        }
+        Err(e) => {
+            eprintln!("Failed to load the kept environment: {}", e);
+            return OverallResult::Failed;
+        }
+    };
+
+    let errs = kept.tear_down(tear_down_order);
+    KeptEnv::remove(workspace_root_dir);
+    if errs.is_empty() {
+        eprintln!("kept environment torn down");
+        OverallResult::Ok
+    } else {
+        for e in errs {
+            eprintln!("{}", e);
+        }
+        OverallResult::Failed
     }
 }
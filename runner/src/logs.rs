@@ -0,0 +1,91 @@
+use std::{
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use dashmap::DashMap;
+
+/// Log files handed out by `log_file_path` and `default_log_file_path`,
+/// by component or test name
+#[derive(Clone, Default)]
+pub(crate) struct LogFiles {
+    by_name: Arc<DashMap<String, Vec<PathBuf>>>,
+}
+
+impl LogFiles {
+    pub fn register(&self, name: &str, path: &Path) {
+        let mut paths = self.by_name.entry(name.to_owned()).or_default();
+        if !paths.iter().any(|p| p == path) {
+            paths.push(path.to_owned());
+        }
+    }
+
+    pub fn of(&self, name: &str) -> Vec<PathBuf> {
+        self.by_name
+            .get(name)
+            .map(|paths| paths.clone())
+            .unwrap_or_default()
+    }
+}
+
+/// The last lines of each log file, headed by its full path
+pub(crate) fn tails(paths: &[PathBuf], num_lines: usize) -> String {
+    let mut out = String::new();
+    if num_lines == 0 {
+        return out;
+    }
+    for path in paths {
+        let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+        let contents = match fs::read(&path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                writeln!(out, "\n--- {} ({}) ---", path.display(), e).unwrap();
+                continue;
+            }
+        };
+        let contents = String::from_utf8_lossy(&contents);
+        let lines: Vec<&str> = contents.lines().collect();
+        if lines.is_empty() {
+            writeln!(out, "\n--- {} (empty) ---", path.display()).unwrap();
+            continue;
+        }
+
+        let tail = &lines[lines.len().saturating_sub(num_lines)..];
+        writeln!(out, "\n--- {} (last {} lines) ---", path.display(), tail.len()).unwrap();
+        for line in tail {
+            writeln!(out, "{}", line).unwrap();
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn only_the_last_lines_are_shown() {
+        let dir = tempfile::tempdir().unwrap();
+        let stdout = dir.path().join("Server.stdout.log");
+        let stderr = dir.path().join("Server.stderr.log");
+        fs::write(&stdout, "").unwrap();
+        fs::write(&stderr, "starting\nbinding 3000\naddress in use\n").unwrap();
+
+        let logs = LogFiles::default();
+        logs.register("Server", &stdout);
+        logs.register("Server", &stderr);
+        logs.register("Server", &stdout);
+
+        assert_eq!(
+            format!(
+                "\n--- {} (empty) ---\n\n--- {} (last 2 lines) ---\nbinding 3000\naddress in use\n",
+                stdout.canonicalize().unwrap().display(),
+                stderr.canonicalize().unwrap().display()
+            ),
+            tails(&logs.of("Server"), 2)
+        );
+        assert!(logs.of("Redis").is_empty());
+    }
+}
@@ -1,28 +1,63 @@
-use crate::{discover::{SetUps, Tests}, progress::{ OverallResult, OverallSummaryBuilder, Phase, PhaseResult, PhaseSummary, ProgressListener, ProgressMonitor},  GlobalContext, TearDown, TearDowns};
+use libtest_mimic::Arguments;
 
+use crate::{discover::{SetUps, Tests}, progress::{ OverallResult, OverallSummaryBuilder, Phase, PhaseResult, PhaseSummary, ProgressListener}, interrupt::Interrupt, keep::{KeptComponent, KeptEnv, KeptHandle}, tasklist::{FailureMode, Task, TaskList},  GlobalContext, ITest, TearDowns};
 
+
+mod panics;
 mod set_up_phase;
 mod test_phase;
 mod tear_down_phase;
 
+pub use set_up_phase::SetUpConcurrency;
+
+#[allow(clippy::too_many_arguments)]
 pub async fn run(
     ctx: &mut GlobalContext,
+    args: Arguments,
+    config: &ITest,
     set_ups: SetUps,
     tests: Tests,
+    kept: Option<KeptEnv>,
+    interrupt: &Interrupt,
     progress: &ProgressListener,
 ) -> OverallResult {
     let mut summary = OverallSummaryBuilder::new();
 
-    let (tear_downs, result) = run_set_ups(ctx, set_ups, progress, &mut summary).await;
+    let (tear_downs, set_up_status, result) =
+        run_set_ups(ctx, &set_ups, config, kept.as_ref(), interrupt, progress, &mut summary).await;
+
+    // when only dependents are skipped, tests that do not need the
+    // failed set ups can still run.
+    let run_tests = !interrupt.is_set()
+        && (result == PhaseResult::Ok || config.failure_mode == FailureMode::SkipDependents);
+
+    if config.env_only && !interrupt.is_set() && result == PhaseResult::Ok {
+        print_params(ctx, &set_ups, &set_up_status, config.keep_env);
+        // a kept environment outlives the run, there is nothing to wait for
+        if !config.keep_env {
+            wait_until_done(interrupt).await;
+        }
+    }
 
-    let test_outcome = if result == PhaseResult::Ok {
-        test_phase::run(tests).await
+    // there are no tests to report on in an env only run
+    if !config.env_only {
+        let test_outcome = if run_tests {
+            test_phase::run(ctx, args, config, tests, &set_ups, &set_up_status, interrupt, progress).await
+        } else {
+            PhaseSummary::skipped(Phase::Test)
+        };
+        summary.add_phase(test_outcome);
+    }
+
+    // a kept environment is only added to when --keep-env is passed again,
+    // otherwise what this run set up is torn down as usual
+    let tear_downs = if config.keep_env && !interrupt.is_set() {
+        keep_env(ctx, &set_ups, &set_up_status, tear_downs, kept.unwrap_or_default())
     } else {
-        PhaseSummary::skipped(Phase::Test)
+        tear_downs
     };
-    summary.add_phase(test_outcome);
 
-    run_tear_downs(tear_downs, progress, &mut summary).await;
+    run_tear_downs(&set_ups, config, tear_downs, progress, &mut summary).await;
 
     let summary = summary.build();
     let result = summary.result;
@@ -33,26 +68,119 @@ pub async fn run(
 
 async fn run_set_ups(
     ctx: &mut GlobalContext,
-    set_ups: SetUps,
+    set_ups: &SetUps,
+    config: &ITest,
+    kept: Option<&KeptEnv>,
+    interrupt: &Interrupt,
     progress: &ProgressListener,
     overall_summary: &mut OverallSummaryBuilder
-)-> (TearDowns, PhaseResult) {
+)-> (TearDowns, TaskList, PhaseResult) {
 
-    let (tear_downs, summary) = set_up_phase::run(set_ups, ctx, progress).await;
+    let (tear_downs, tasks, summary) = set_up_phase::run(set_ups, ctx, config, kept, interrupt, progress).await;
 
     let result = summary.result;
 
     overall_summary.add_phase(summary.clone());
 
-    (tear_downs, result)
+    (tear_downs, tasks, result)
+}
+
+/// Params of the components that were set up, for `--env-only`
+fn print_params(ctx: &GlobalContext, set_ups: &SetUps, set_up_status: &TaskList, keep_env: bool) {
+    println!("\nenvironment is up, params:");
+    for (task, name) in set_ups.tasks() {
+        if !set_up_status.succeeded(task) {
+            continue;
+        }
+        for (key, value) in ctx.component_params(name) {
+            println!("    {} = {}", key, value);
+        }
+    }
+    if !keep_env {
+        println!("\npress Ctrl-C or close stdin (Ctrl-D) to tear down");
+    }
+}
+
+/// Wait for Ctrl-C, SIGTERM or the end of stdin
+async fn wait_until_done(interrupt: &Interrupt) {
+    let stdin_closed = tokio::task::spawn_blocking(|| {
+        let _ = std::io::copy(&mut std::io::stdin().lock(), &mut std::io::sink());
+    });
+    interrupt.run_until(stdin_closed).await;
+}
+
+/// Record the successful components in the kept environment instead of
+/// tearing them down, returns what still has to be torn down
+fn keep_env(
+    ctx: &GlobalContext,
+    set_ups: &SetUps,
+    set_up_status: &TaskList,
+    mut tear_downs: TearDowns,
+    mut kept: KeptEnv,
+) -> TearDowns {
+    let deps = set_ups.dep_table.dep_lists();
+    // dependencies first, a component is only kept when everything it
+    // requires is, otherwise a later run would reuse it with fresh ones
+    let mut order = set_ups.tear_down_order();
+    order.reverse();
+    for name in order {
+        let Some(id) = set_ups.dep_table.find(name) else {
+            continue;
+        };
+        let task = Task(id);
+        // reused ones are kept already
+        if kept.components.contains_key(name) || !set_up_status.succeeded(task) {
+            continue;
+        }
+
+        let tear_down = tear_downs.remove(task);
+        let handles = match &tear_down {
+            Some(tear_down) => tear_down.keep_handles(),
+            None => Some(Vec::new()),
+        };
+        let requires_kept = deps[id]
+            .iter()
+            .all(|dep| kept.components.contains_key(set_ups.dep_table.name(*dep)));
+        match handles {
+            Some(handles) if requires_kept => {
+                let component = KeptComponent {
+                    params: ctx.component_params(name),
+                    handles: handles.into_iter().map(KeptHandle::record).collect(),
+                    resource: ctx.provides_resource(name),
+                };
+                kept.components.insert(name.to_owned(), component);
+                // dropping the tear down could stop the component, e.g. a
+                // container is removed when its handle is dropped
+                std::mem::forget(tear_down);
+            }
+            handles => {
+                if handles.is_some() {
+                    eprintln!("#[set_up({})] is torn down, a set up it requires is not kept", name);
+                } else {
+                    eprintln!("#[set_up({})] is torn down, it can not be kept", name);
+                }
+                if let Some(tear_down) = tear_down {
+                    tear_downs.push(task, tear_down);
+                }
+            }
+        }
+    }
+
+    match kept.save(ctx.workspace_root_dir()) {
+        Ok(()) => eprintln!("environment kept, run with --tear-down-env to tear it down"),
+        Err(e) => eprintln!("Failed to save the kept environment: {}", e),
+    }
+    tear_downs
 }
 
 async fn run_tear_downs(
+    set_ups: &SetUps,
+    config: &ITest,
     tear_downs: TearDowns,
     progress: &ProgressListener,
     overall_summary: &mut OverallSummaryBuilder
 ) {
-    let summary = tear_down_phase::run(progress, tear_downs).await;
+    let summary = tear_down_phase::run(set_ups, config, progress, tear_downs).await;
     
     overall_summary.add_phase(summary);
 }
\ No newline at end of file
@@ -0,0 +1,82 @@
+use std::{
+    any::Any,
+    backtrace::{Backtrace, BacktraceStatus},
+    cell::{Cell, RefCell},
+    panic::{self, AssertUnwindSafe},
+    pin::pin,
+    sync::Once,
+};
+
+use futures::{FutureExt, future::poll_fn};
+
+/// Where a panic happened, as seen by the panic hook
+pub(super) struct PanicDetails {
+    pub message: String,
+    pub location: Option<String>,
+    pub backtrace: Option<String>,
+}
+
+thread_local! {
+    static CAPTURING: Cell<bool> = const { Cell::new(false) };
+    static CAPTURED: RefCell<Option<(Option<String>, Option<String>)>> = const { RefCell::new(None) };
+}
+
+static INSTALL_HOOK: Once = Once::new();
+
+/// Record the location and backtrace of panics inside [`catch`] instead of
+/// printing them, other panics go to the previous hook.
+fn install_hook() {
+    INSTALL_HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if !CAPTURING.get() {
+                return previous(info);
+            }
+            let location = info.location().map(|l| l.to_string());
+            let backtrace = Backtrace::capture();
+            let backtrace = match backtrace.status() {
+                BacktraceStatus::Captured => Some(backtrace.to_string()),
+                _ => None,
+            };
+            CAPTURED.replace(Some((location, backtrace)));
+        }));
+    });
+}
+
+/// Message of a panic payload, when it is a string
+pub(super) fn panic_message(e: &(dyn Any + Send)) -> Option<String> {
+    e.downcast_ref::<String>()
+        .cloned()
+        .or(e.downcast_ref::<&str>().map(|s| s.to_string()))
+}
+
+fn details(payload: Box<dyn Any + Send>) -> PanicDetails {
+    let (location, backtrace) = CAPTURED.take().unwrap_or_default();
+    PanicDetails {
+        message: panic_message(payload.as_ref()).unwrap_or_else(|| "Box<dyn Any>".to_owned()),
+        location,
+        backtrace,
+    }
+}
+
+/// Create and drive the future, catching panics in either step
+pub(super) async fn catch<T, F>(create: impl FnOnce() -> F) -> Result<T, PanicDetails>
+where
+    F: Future<Output = T>,
+{
+    install_hook();
+
+    CAPTURING.set(true);
+    let future = panic::catch_unwind(AssertUnwindSafe(create));
+    CAPTURING.set(false);
+
+    let mut future = pin!(AssertUnwindSafe(future.map_err(details)?).catch_unwind());
+    poll_fn(|cx| {
+        CAPTURING.set(true);
+        let poll = future.as_mut().poll(cx);
+        CAPTURING.set(false);
+        poll
+    })
+    .await
+    .map_err(details)
+}
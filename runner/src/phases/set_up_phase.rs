@@ -1,26 +1,93 @@
-use futures::FutureExt;
 use std::{
-    any::Any,
-    panic::{self, AssertUnwindSafe},
+    cmp::Reverse,
+    fmt,
+    num::NonZeroUsize,
+    str::FromStr,
     time::{Duration, Instant},
 };
 
 use async_channel::Receiver;
 use tokio::sync::mpsc;
 
+use super::panics;
 use crate::{
-    Context, GlobalContext, SetUpError, SetUpFn, SetUpResult, TearDown, TearDowns,
+    Context, GlobalContext, ITest, SetUpError, SetUpFn, SetUpResult, TearDown, TearDowns,
     discover::SetUps,
+    error::Report,
     progress::{Phase, PhaseSummary, PhaseSummaryBuilder, ProgressListener, TaskStatus},
-    tasklist::{Status, Task},
+    budget::Allocator,
+    history::Durations,
+    interrupt::Interrupt,
+    keep::KeptEnv,
+    logs,
+    retry::Retry,
+    tasklist::{Status, Task, TaskList},
 };
 
+/// How many set ups may run at the same time
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SetUpConcurrency {
+    /// At most this many, a single worker runs the set ups one after another
+    Workers(NonZeroUsize),
+    /// Every set up starts as soon as its dependencies are done
+    Unbounded,
+}
+
+impl Default for SetUpConcurrency {
+    fn default() -> Self {
+        SetUpConcurrency::Workers(NonZeroUsize::new(3).unwrap())
+    }
+}
+
+impl SetUpConcurrency {
+    pub(super) fn num_workers(self, num_tasks: usize) -> usize {
+        match self {
+            SetUpConcurrency::Workers(n) => n.get(),
+            SetUpConcurrency::Unbounded => num_tasks.max(1),
+        }
+    }
+}
+
+impl FromStr for SetUpConcurrency {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "unbounded" => Ok(SetUpConcurrency::Unbounded),
+            _ => match s.parse::<NonZeroUsize>() {
+                Ok(n) => Ok(SetUpConcurrency::Workers(n)),
+                Err(_) => Err(format!(
+                    "invalid set up concurrency `{}`, expected a positive number or unbounded",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+impl fmt::Display for SetUpConcurrency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SetUpConcurrency::Workers(n) => write!(f, "{}", n),
+            SetUpConcurrency::Unbounded => f.write_str("unbounded"),
+        }
+    }
+}
+
 pub async fn run(
-    set_ups: SetUps,
+    set_ups: &SetUps,
     global_ctx: &mut GlobalContext,
+    config: &ITest,
+    kept: Option<&KeptEnv>,
+    interrupt: &Interrupt,
     progress: &ProgressListener,
-) -> (TearDowns, PhaseSummary) {
-    let mut workers = launch_set_up_workers(3, progress.clone());
+) -> (TearDowns, TaskList, PhaseSummary) {
+    let num_workers = config.set_up_concurrency.num_workers(set_ups.tasks().count());
+    let mut workers = launch_set_up_workers(num_workers, interrupt.clone(), progress.clone());
+
+    // ready tasks wait here until a worker and their cost are available
+    let mut allocator = Allocator::new(config.budget, num_workers);
+    let mut pending = Vec::new();
 
     let mut tear_downs = TearDowns::default();
     let mut errs: Vec<SetUpError> = Vec::new();
@@ -29,139 +96,404 @@ pub async fn run(
         .phase_started(Phase::SetUp, set_ups.tasks().count())
         .await;
 
-    let mut tasks = set_ups.make_task_list();
+    // start the set ups on the slowest chains first, based on earlier runs
+    let mut history = Durations::load(global_ctx.workspace_root_dir());
+    let weights = history.weights((0..set_ups.tasks().count()).map(|id| set_ups.dep_table.name(id)));
+    let mut tasks = set_ups
+        .make_task_list()
+        .with_failure_mode(config.failure_mode)
+        .with_weights(&weights);
+
+    let mut summary = PhaseSummaryBuilder::new(Phase::SetUp);
 
     // push the task that are ready to go
-    if let Some(ready) = tasks.pop_ready() {
-        for task in ready {
-            let ctx = global_ctx.create_component_context(set_ups.dep_table.name(task.0));
-            let set_up = set_ups.dep_table.decl(task.0).set_up_fn;
-            workers.push(task, set_up, ctx).await;
-        }
-    }
+    let mut in_flight = 0;
+    pending.extend(pop_ready(&mut tasks, set_ups, global_ctx, kept, progress, &mut summary).await);
+    in_flight += dispatch(&mut pending, &mut allocator, config, set_ups, global_ctx, &workers).await;
 
-    let mut summary = PhaseSummaryBuilder::new(Phase::SetUp);
+    while !tasks.none_waiting() {
+        // once interrupted, only wait for the running set ups to be cancelled
+        if interrupt.is_set() && in_flight == 0 {
+            break;
+        }
+        let Some((task, result, duration, mut registered)) = workers.pull_result().await else {
+            break;
+        };
+        in_flight -= 1;
+        let decl = set_ups.dep_table.decl(task.0);
+        allocator.release(decl.cost, decl.exclusive);
 
-    while let Some((task, result)) = workers.pull_result().await {
         match result {
             Ok(out) => {
-                if let Some(tear_down) = out {
-                    tear_downs.push(task, tear_down);
-                }
+                registered.extend(out);
+                tear_downs.push_all(task, registered);
+                history.record(set_ups.dep_table.name(task.0), duration);
                 tasks.set_status(task, Status::Success);
                 summary.inc(TaskStatus::Ok);
             }
             Err(e) => {
+                // cleanup registered before the failure still has to run
+                tear_downs.push_all(task, registered);
                 tasks.set_status(task, Status::Failed);
                 errs.push(e);
                 summary.inc(TaskStatus::Failed);
             }
         }
 
-        if let Some(ready) = tasks.pop_ready() {
-            for task in ready {
-                let ctx = global_ctx.create_component_context(set_ups.dep_table.name(task.0));
-                let set_up = set_ups.dep_table.decl(task.0).set_up_fn;
-                workers.push(task, set_up, ctx).await;
+        if let Some(skipped) = tasks.pop_skipped() {
+            for task in skipped {
+                let cause = tasks.failure_cause(task).unwrap_or(task);
+                let reason = SetUpError::DependencyFailed {
+                    dependency: set_ups.dep_table.name(cause.0).to_owned(),
+                }
+                .to_string();
+                progress.task_skipped(Phase::SetUp, task, reason).await;
+                summary.inc(TaskStatus::Skipped);
             }
         }
 
-        if tasks.none_waiting() {
-            break;
+        if interrupt.is_set() {
+            continue;
         }
+        pending.extend(pop_ready(&mut tasks, set_ups, global_ctx, kept, progress, &mut summary).await);
+        pending.sort_by_key(|t| Reverse(tasks.priority(*t)));
+        in_flight += dispatch(&mut pending, &mut allocator, config, set_ups, global_ctx, &workers).await;
     }
 
+    if interrupt.is_set() {
+        pending.clear();
+        for task in tasks.cancel_remaining() {
+            progress
+                .task_skipped(Phase::SetUp, task, "interrupted".to_owned())
+                .await;
+            summary.inc(TaskStatus::Skipped);
+        }
+    }
+
+    history.save(global_ctx.workspace_root_dir());
+
     let summary = summary.build();
     progress.phase_finished(summary.clone()).await;
 
-    (tear_downs, summary)
+    (tear_downs, tasks, summary)
+}
+
+/// Tasks that are ready to be set up. Components kept by an earlier run are
+/// reused instead, which can make their dependents ready straight away.
+async fn pop_ready(
+    tasks: &mut TaskList,
+    set_ups: &SetUps,
+    global_ctx: &mut GlobalContext,
+    kept: Option<&KeptEnv>,
+    progress: &ProgressListener,
+    summary: &mut PhaseSummaryBuilder,
+) -> Vec<Task> {
+    let mut ready = Vec::new();
+    while let Some(popped) = tasks.pop_ready() {
+        for task in popped {
+            let name = set_ups.dep_table.name(task.0);
+            match kept.and_then(|kept| kept.components.get(name)) {
+                Some(component) => {
+                    global_ctx.reuse_component(name, &component.params);
+                    tasks.set_status(task, Status::Success);
+                    progress.task_reused(Phase::SetUp, task).await;
+                    summary.inc(TaskStatus::Reused);
+                }
+                None => ready.push(task),
+            }
+        }
+    }
+    ready
+}
+
+/// Hand the pending tasks that fit within the budget to the workers,
+/// returns how many were started
+async fn dispatch(
+    pending: &mut Vec<Task>,
+    allocator: &mut Allocator,
+    config: &ITest,
+    set_ups: &SetUps,
+    global_ctx: &mut GlobalContext,
+    workers: &SetUpWorkers,
+) -> usize {
+    let mut started = 0;
+    let mut waiting = Vec::new();
+    for task in pending.drain(..) {
+        let decl = set_ups.dep_table.decl(task.0);
+        if allocator.try_acquire(decl.cost, decl.exclusive) {
+            let ctx = global_ctx.create_component_context(set_ups.dep_table.name(task.0));
+            workers
+                .push(SetUpJob {
+                    task,
+                    set_up: decl.set_up_fn,
+                    ctx,
+                    timeout: decl.timeout.or(config.set_up_timeout),
+                    retry: decl.retry,
+                    origin: format!("#[set_up({})] at {}", set_ups.dep_table.name(task.0), decl),
+                    log_tail_lines: config.log_tail_lines(),
+                })
+                .await;
+            started += 1;
+        } else {
+            waiting.push(task);
+        }
+    }
+    *pending = waiting;
+    started
 }
 
-fn launch_set_up_workers(num_workers: usize, progress: ProgressListener) -> SetUpWorkers {
+fn launch_set_up_workers(
+    num_workers: usize,
+    interrupt: Interrupt,
+    progress: ProgressListener,
+) -> SetUpWorkers {
     let (run_tx, run_rx) = async_channel::unbounded();
     let (result_tx, result_rx) = mpsc::channel(100);
     for _ in 1..=num_workers {
-        let run_rx: Receiver<(Task, &'static SetUpFn, Context)> = run_rx.clone();
+        let run_rx: Receiver<SetUpJob> = run_rx.clone();
         let result_tx = result_tx.clone();
         let progress = progress.clone();
-        tokio::spawn(async move { run_set_up_worker(run_rx, result_tx, progress).await });
+        let interrupt = interrupt.clone();
+        tokio::spawn(async move { run_set_up_worker(run_rx, result_tx, interrupt, progress).await });
     }
 
     SetUpWorkers { run_tx, result_rx }
 }
 
 async fn run_set_up_worker(
-    run_rx: Receiver<(Task, &'static SetUpFn, Context)>,
-    result_tx: mpsc::Sender<(Task, SetUpResult)>,
+    run_rx: Receiver<SetUpJob>,
+    result_tx: mpsc::Sender<SetUpOutcome>,
+    interrupt: Interrupt,
     progress: ProgressListener,
 ) {
-    while let Ok((task, set_up, ctx)) = run_rx.recv().await {
-        let result = run_task(task, set_up, ctx, &progress).await;
-        if let Some(err) = result_tx.send((task, result)).await.err() {
+    while let Ok(job) = run_rx.recv().await {
+        let task = job.task;
+        let ctx = job.ctx.clone();
+        let start = Instant::now();
+        // dropping the set up cancels it at its next await point
+        let (result, duration) = match interrupt.run_until(run_task(job, &progress)).await {
+            Some(outcome) => outcome,
+            None => {
+                let duration = start.elapsed();
+                let err = SetUpError::Interrupted;
+                progress
+                    .task_failed(Phase::SetUp, task, duration, err.to_string())
+                    .await;
+                (Err(err), duration)
+            }
+        };
+        let registered = ctx.take_tear_downs();
+        if let Some(err) = result_tx.send((task, result, duration, registered)).await.err() {
             eprintln!("Failed to publish result of task {task:?} {err:?}");
         }
     }
 }
 
-async fn run_task(
-    task: Task,
-    set_up: &'static SetUpFn,
-    ctx: Context,
-    progress: &ProgressListener,
-) -> SetUpResult {
+async fn run_task(job: SetUpJob, progress: &ProgressListener) -> (SetUpResult, Duration) {
+    let task = job.task;
+    let attempts = job.retry.retries + 1;
+    let mut errs = Vec::new();
 
-    progress.task_running(Phase::SetUp, task).await;
+    for attempt in 1..=attempts {
+        progress.task_running(Phase::SetUp, task).await;
 
-    let start = Instant::now();
-    let result = safe_run_task(set_up, ctx).await;
-    let duration = start.elapsed();
-
-    match &result {
-        Ok(_) => {
-            progress.task_done(Phase::SetUp, task, duration).await;
+        let (result, duration) = run_attempt(&job, job.ctx.clone()).await;
+        if result.is_err() {
+            job.ctx.discard_attempt();
         }
-        Err(err) => {
-            progress
-                .task_failed(Phase::SetUp, task, duration, format!("{:?}", err))
-                .await;
+        match result {
+            Ok(out) => {
+                progress.task_done(Phase::SetUp, task, duration).await;
+                return (Ok(out), duration);
+            }
+            Err(err) if attempt < attempts => {
+                let delay = job.retry.delay(attempt);
+                let msg = format!(
+                    "{}\nattempt {} of {} failed, retrying in {:.02}s: {}",
+                    job.origin,
+                    attempt,
+                    attempts,
+                    delay.as_millis() as f64 / 1000.0,
+                    Report(&err)
+                );
+                progress.task_retrying(Phase::SetUp, task, duration, msg).await;
+                errs.push(err);
+                tokio::time::sleep(delay).await;
+            }
+            Err(err) => {
+                let err = if errs.is_empty() {
+                    err
+                } else {
+                    errs.push(err);
+                    SetUpError::RetriesExhausted(errs)
+                };
+                let msg = format!(
+                    "{}\n{}\n{}",
+                    job.origin,
+                    Report(&err),
+                    logs::tails(&job.ctx.log_files(), job.log_tail_lines)
+                );
+                progress
+                    .task_failed(Phase::SetUp, task, duration, msg.trim_end().to_owned())
+                    .await;
+                return (Err(err), duration);
+            }
         }
     }
-
-    result
+    unreachable!("a set up is attempted at least once")
 }
 
-fn panic_err(e: Box<dyn Any + Send>) -> SetUpError {
-    SetUpError::Generic(format!("Setup panicked during execution: {:?}", e))
+/// Run the set up once, the timeout applies to every attempt separately
+async fn run_attempt(job: &SetUpJob, ctx: Context) -> (SetUpResult, Duration) {
+    let start = Instant::now();
+    // dropping the future on expiry cancels the set up at its next await
+    // point, a set up blocking the thread can not be interrupted.
+    let result = match job.timeout {
+        Some(timeout) => tokio::time::timeout(timeout, safe_run_task(job.set_up, ctx))
+            .await
+            .unwrap_or_else(|_| Err(SetUpError::Timeout(start.elapsed()))),
+        None => safe_run_task(job.set_up, ctx).await,
+    };
+    (result, start.elapsed())
 }
 
-
 async fn safe_run_task(set_up: &'static SetUpFn, ctx: Context) -> SetUpResult {
+    panics::catch(|| (*set_up)(ctx))
+        .await
+        .unwrap_or_else(|panic| {
+            Err(SetUpError::Panicked {
+                message: panic.message,
+                location: panic.location,
+                backtrace: panic.backtrace,
+            })
+        })
+}
 
-    let future = panic::catch_unwind(AssertUnwindSafe(|| (*set_up)(ctx)));
-
-    match future {
-
-        Ok(future) => AssertUnwindSafe(future)
-            .catch_unwind()
-            .await
-            .map_err(|e| panic_err(e))
-            .flatten(),
+/// Result of a set up, with the tear downs it registered through
+/// [`Context::on_tear_down`]
+type SetUpOutcome = (Task, SetUpResult, Duration, Vec<Box<dyn TearDown>>);
 
-        Err(e) => Err(panic_err(e)),
-    }
+/// A set up handed to a worker
+struct SetUpJob {
+    task: Task,
+    set_up: &'static SetUpFn,
+    ctx: Context,
+    timeout: Option<Duration>,
+    retry: Retry,
+    /// `#[set_up(Name)] at file:line`, shown with the errors
+    origin: String,
+    log_tail_lines: usize,
 }
 
 struct SetUpWorkers {
-    run_tx: async_channel::Sender<(Task, &'static SetUpFn, Context)>,
-    result_rx: mpsc::Receiver<(Task, SetUpResult)>,
+    run_tx: async_channel::Sender<SetUpJob>,
+    result_rx: mpsc::Receiver<SetUpOutcome>,
 }
 
 impl SetUpWorkers {
-    pub async fn push(&self, task: Task, set_up_fn: &'static SetUpFn, ctx: Context) {
-        self.run_tx.send((task, set_up_fn, ctx)).await.unwrap();
+    pub async fn push(&self, job: SetUpJob) {
+        self.run_tx.send(job).await.unwrap();
     }
 
-    pub async fn pull_result(&mut self) -> Option<(Task, SetUpResult)> {
+    pub async fn pull_result(&mut self) -> Option<SetUpOutcome> {
         self.result_rx.recv().await
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn concurrency_can_be_parsed() {
+        assert_eq!(Ok(SetUpConcurrency::Workers(NonZeroUsize::MIN)), "1".parse());
+        assert_eq!(Ok(SetUpConcurrency::Unbounded), "unbounded".parse());
+        assert_eq!(
+            Err("invalid set up concurrency `0`, expected a positive number or unbounded".to_owned()),
+            "0".parse::<SetUpConcurrency>()
+        );
+    }
+
+    /// Run a single set up the way a worker does, returns its context too
+    fn run_job(set_up: &'static SetUpFn, timeout: Option<Duration>, retry: Retry) -> (SetUpResult, Context) {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let mut global_ctx = GlobalContext::new(dir.path());
+        let ctx = global_ctx.create_component_context("Flaky");
+        let job = SetUpJob {
+            task: Task(0),
+            set_up,
+            ctx: ctx.clone(),
+            timeout,
+            retry,
+            origin: "#[set_up(Flaky)]".to_owned(),
+            log_tail_lines: 0,
+        };
+
+        let (result, _) = rt.block_on(async { run_task(job, &ProgressListener::discard()).await });
+        (result, ctx)
+    }
+
+    fn failed(msg: &str) -> SetUpError {
+        SetUpError::from(Box::<dyn std::error::Error>::from(msg))
+    }
+
+    #[test]
+    fn retries_do_not_see_params_of_failed_attempts() {
+        use crate::{SetFnOutput, retry::Backoff};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static ATTEMPTS: AtomicUsize = AtomicUsize::new(0);
+        static FLAKY: SetUpFn = flaky;
+        fn flaky(ctx: Context) -> SetFnOutput {
+            Box::pin(async move {
+                if ctx.get_param("Flaky.url").is_ok() {
+                    return Err(failed("url of the failed attempt"));
+                }
+                ctx.set_param("url", "http://localhost:3000/");
+                match ATTEMPTS.fetch_add(1, Ordering::SeqCst) {
+                    0 => Err(failed("not yet")),
+                    _ => Ok(None),
+                }
+            })
+        }
+
+        let retry = Retry {
+            retries: 1,
+            backoff: Backoff::Fixed(Duration::ZERO),
+        };
+        let (result, _) = run_job(&FLAKY, None, retry);
+        assert!(result.is_ok());
+        assert_eq!(2, ATTEMPTS.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn tear_downs_registered_before_a_timeout_are_kept() {
+        use crate::{SetFnOutput, TearDownResult};
+        use async_trait::async_trait;
+
+        struct Container;
+
+        #[async_trait]
+        impl TearDown for Container {
+            async fn tear_down(&mut self) -> TearDownResult {
+                Ok(())
+            }
+        }
+
+        static HANGS: SetUpFn = hangs;
+        fn hangs(ctx: Context) -> SetFnOutput {
+            Box::pin(async move {
+                ctx.on_tear_down(Container);
+                std::future::pending().await
+            })
+        }
+
+        let (result, ctx) = run_job(&HANGS, Some(Duration::from_millis(20)), Retry::NONE);
+        assert!(matches!(result, Err(SetUpError::Timeout(_))));
+        assert_eq!(1, ctx.take_tear_downs().len());
+    }
+}
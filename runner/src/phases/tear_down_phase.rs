@@ -1,24 +1,77 @@
 use futures::FutureExt;
 use std::{
     any::Any,
-    panic::{ AssertUnwindSafe},
-    time::{Instant},
+    panic::AssertUnwindSafe,
+    time::{Duration, Instant},
 };
 
+use async_channel::Receiver;
+use tokio::sync::mpsc;
+
+use super::panics::panic_message;
 use crate::{
-    TearDown, TearDownResult, TearDowns, progress::{Phase, PhaseSummary, PhaseSummaryBuilder, ProgressListener, TaskStatus}, tasklist::Task
+    ITest, TearDown, TearDownResult, TearDowns,
+    discover::SetUps,
+    progress::{Phase, PhaseSummary, PhaseSummaryBuilder, ProgressListener, TaskStatus},
+    tasklist::{FailureMode, Status, Task},
 };
 
-pub async fn run(progress: &ProgressListener, mut tear_downs: TearDowns) -> PhaseSummary {
-
+/// Tear down in reverse dependency order, a component is only torn down once
+/// everything depending on it has been. Independent components are torn down
+/// concurrently.
+pub async fn run(
+    set_ups: &SetUps,
+    config: &ITest,
+    progress: &ProgressListener,
+    mut tear_downs: TearDowns,
+) -> PhaseSummary {
     let mut summary = PhaseSummaryBuilder::new(Phase::TearDown);
 
     progress
         .phase_started(Phase::TearDown, tear_downs.len())
         .await;
 
-    while let Some((task, tear_down)) = tear_downs.pop() {
-        run_task(task, &mut summary, tear_down, progress).await;
+    let num_workers = config.set_up_concurrency.num_workers(tear_downs.len());
+    let mut workers = launch_tear_down_workers(num_workers, progress.clone());
+
+    // a failed tear down must not stop the components it depends on from
+    // being torn down
+    let mut tasks = set_ups
+        .dep_table
+        .make_reverse_task_list()
+        .with_failure_mode(FailureMode::SkipDependents);
+
+    loop {
+        while let Some(ready) = tasks.pop_ready() {
+            for task in ready {
+                match tear_downs.remove(task) {
+                    Some(tear_down) => workers.push(task, tear_down).await,
+                    // nothing to tear down, or the set up never ran
+                    None => tasks.set_status(task, Status::Success),
+                }
+            }
+        }
+
+        if tasks.none_waiting() {
+            break;
+        }
+
+        let Some((task, result, duration)) = workers.pull_result().await else {
+            break;
+        };
+
+        match result {
+            Ok(()) => {
+                tasks.set_status(task, Status::Success);
+                summary.inc(TaskStatus::Ok);
+                progress.task_done(Phase::TearDown, task, duration).await;
+            }
+            Err(e) => {
+                tasks.set_status(task, Status::Failed);
+                summary.inc(TaskStatus::Failed);
+                progress.task_failed(Phase::TearDown, task, duration, e).await
+            }
+        }
     }
 
     let summary = summary.build();
@@ -27,36 +80,43 @@ pub async fn run(progress: &ProgressListener, mut tear_downs: TearDowns) -> Phas
     summary
 }
 
-async fn run_task(
-    task: Task,
-    summary: &mut PhaseSummaryBuilder,
-    tear_down: Box<dyn TearDown + 'static>,
-    progress: &ProgressListener,
-)  {
+fn launch_tear_down_workers(num_workers: usize, progress: ProgressListener) -> TearDownWorkers {
+    let (run_tx, run_rx) = async_channel::unbounded();
+    let (result_tx, result_rx) = mpsc::channel(100);
+    for _ in 1..=num_workers {
+        let run_rx: Receiver<(Task, Box<dyn TearDown>)> = run_rx.clone();
+        let result_tx = result_tx.clone();
+        let progress = progress.clone();
+        tokio::spawn(async move { run_tear_down_worker(run_rx, result_tx, progress).await });
+    }
 
-    progress.task_running(Phase::TearDown, task).await;
+    TearDownWorkers { run_tx, result_rx }
+}
 
-    let start = Instant::now();
-    let result = safe_run_task(tear_down).await;
-    let duration = start.elapsed();
+async fn run_tear_down_worker(
+    run_rx: Receiver<(Task, Box<dyn TearDown>)>,
+    result_tx: mpsc::Sender<(Task, Result<(), String>, Duration)>,
+    progress: ProgressListener,
+) {
+    while let Ok((task, tear_down)) = run_rx.recv().await {
+        progress.task_running(Phase::TearDown, task).await;
 
-    match &result {
-        Ok(()) => {
-            summary.inc(TaskStatus::Ok);
-            progress.task_done(Phase::TearDown, task, duration).await;
-        }
-        Err(e) => {
-            summary.inc(TaskStatus::Failed);
-            progress
-                .task_failed(Phase::TearDown, task, duration, format!("{:?}", e))
-                .await
+        let start = Instant::now();
+        // errors are not Send, so only their message is passed on
+        let result = safe_run_task(tear_down).await.map_err(|e| e.to_string());
+        let duration = start.elapsed();
+
+        if let Some(err) = result_tx.send((task, result, duration)).await.err() {
+            eprintln!("Failed to publish result of task {task:?} {err:?}");
         }
     }
 }
 
-
 fn panic_err(e: Box<dyn Any + Send>) -> Box<dyn  std::error::Error> {
-    format!("Setup panicked during execution: {:?}", e).into()
+    match panic_message(e.as_ref()) {
+        Some(message) => format!("tear down panicked: {}", message).into(),
+        None => "tear down panicked".into(),
+    }
 }
 
 
@@ -65,4 +125,21 @@ async fn safe_run_task(mut tear_down: Box<dyn TearDown + 'static>) -> TearDownRe
         Ok(result) => result,
         Err(panic) => Err(panic_err(panic)),
     }
-}
\ No newline at end of file
+}
+
+struct TearDownWorkers {
+    run_tx: async_channel::Sender<(Task, Box<dyn TearDown>)>,
+    result_rx: mpsc::Receiver<(Task, Result<(), String>, Duration)>,
+}
+
+impl TearDownWorkers {
+    pub async fn push(&self, task: Task, tear_down: Box<dyn TearDown>) {
+        if self.run_tx.send((task, tear_down)).await.is_err() {
+            panic!("tear down workers stopped");
+        }
+    }
+
+    pub async fn pull_result(&mut self) -> Option<(Task, Result<(), String>, Duration)> {
+        self.result_rx.recv().await
+    }
+}
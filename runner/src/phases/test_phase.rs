@@ -1,27 +1,318 @@
-use libtest_mimic::{Arguments, Trial};
+use futures::FutureExt;
+use libtest_mimic::{Arguments, Failed, Trial};
+use std::{
+    collections::{BTreeSet, HashSet},
+    path::PathBuf,
+    error::Error,
+    fmt::Write,
+    panic::{self, AssertUnwindSafe},
+    thread,
+    time::Duration,
+};
+use tokio::runtime::Handle;
 
-use crate::{discover::Tests, progress::{Phase, PhaseSummary, PhaseSummaryBuilder, TaskStatus}};
+use super::panics::panic_message;
+use crate::{
+    GlobalContext, ITest, ShouldPanic, TestContext, TestFn, TestFnOutput, TestResult,
+    discover::{SetUps, TestDecl, Tests},
+    interrupt::Interrupt,
+    logs,
+    progress::{Phase, PhaseSummary, PhaseSummaryBuilder, ProgressListener, TaskStatus},
+    tasklist::{Task, TaskList},
+};
 
-
-
-pub async fn run(tests: Tests)-> PhaseSummary {
-    let args = Arguments::from_args();
+#[allow(clippy::too_many_arguments)]
+pub async fn run(
+    global_ctx: &GlobalContext,
+    args: Arguments,
+    config: &ITest,
+    tests: Tests,
+    set_ups: &SetUps,
+    set_up_status: &TaskList,
+    interrupt: &Interrupt,
+    progress: &ProgressListener,
+) -> PhaseSummary {
     let mut trials = Vec::new();
+    let mut skipped = Vec::new();
 
     let mut bld = PhaseSummaryBuilder::new(Phase::Test);
 
+    let handle = Handle::current();
+
+    let selected: HashSet<String> = tests
+        .selected(&args)
+        .into_iter()
+        .map(|t| t.name.to_owned())
+        .collect();
+
+    let ignored: Vec<(String, String)> = tests
+        .ignored(&args)
+        .into_iter()
+        .filter_map(|t| Some((t.name.to_owned(), t.ignore.clone()?)))
+        .filter(|(_, reason)| !reason.is_empty())
+        .collect();
+
     for test in tests.tests {
-        trials.push(Trial::test(test.name.to_owned(), move || {
-            (test.test_fn)();
-            Ok(())
-        }));
+        let blocked_by = if selected.contains(&test.name) {
+            failed_set_up(set_ups, set_up_status, &test)
+        } else {
+            None
+        };
+
+        if let Some(cause) = blocked_by {
+            let reason = format!("depends on failed set up {}", set_ups.dep_table.name(cause.0));
+            skipped.push((test.name, reason));
+            continue;
+        }
+
+        // logs of the test itself and of the set ups it declares, a test
+        // without dependencies would otherwise show the logs of every set up
+        let deps = if test.deps.is_empty() {
+            BTreeSet::new()
+        } else {
+            set_ups.required_by(&test)
+        };
+        let log_names: Vec<String> = std::iter::once(test.name.clone())
+            .chain(deps.into_iter().map(|id| set_ups.dep_table.name(id).to_owned()))
+            .collect();
+        let log_files = global_ctx.log_files();
+        let log_tail_lines = config.log_tail_lines();
+
+        let handle = handle.clone();
+        let interrupt = interrupt.clone();
+        let ctx = global_ctx.create_test_context(&test.name);
+        let test_fn = *test.test_fn;
+        let should_panic = test.should_panic;
+        let timeout = test.timeout;
+        let trial = Trial::test(test.name.to_owned(), move || {
+            if interrupt.is_set() {
+                return Err("interrupted".into());
+            }
+            let result = run_test(test_fn, ctx, timeout, &interrupt, &handle);
+            check_outcome(result, should_panic).map_err(|failed| {
+                let paths: Vec<PathBuf> = log_names.iter().flat_map(|name| log_files.of(name)).collect();
+                let tails = logs::tails(&paths, log_tail_lines);
+                if tails.is_empty() {
+                    failed
+                } else {
+                    format!("{}\n{}", failed.message().unwrap_or("test failed"), tails.trim_end()).into()
+                }
+            })
+        });
+        trials.push(trial.with_ignored_flag(test.ignore.is_some()));
     }
 
-    let conclusion = libtest_mimic::run(&args, trials);
+    // libtest runs the trials on blocking threads, so async tests can be
+    // driven from there using the runtime handle.
+    let conclusion = tokio::task::spawn_blocking(move || libtest_mimic::run(&args, trials))
+        .await
+        .unwrap();
+
+    bld.add(conclusion.num_passed as usize, TaskStatus::Ok);
+    bld.add(conclusion.num_ignored as usize, TaskStatus::Ignored);
+    bld.add(conclusion.num_failed as usize, TaskStatus::Failed);
+
+    for (name, reason) in skipped {
+        progress.test_not_run(name, TaskStatus::Skipped, reason).await;
+        bld.inc(TaskStatus::Skipped);
+    }
 
-    bld.add( conclusion.num_passed as usize, TaskStatus::Ok);
-    bld.add( conclusion.num_ignored as usize, TaskStatus::Skipped);
-    bld.add( conclusion.num_failed  as usize, TaskStatus::Failed);
+    for (name, reason) in ignored {
+        progress.test_not_run(name, TaskStatus::Ignored, reason).await;
+    }
 
     bld.build()
- }
\ No newline at end of file
+}
+
+/// The failed set up preventing this test from running (if any)
+fn failed_set_up(set_ups: &SetUps, set_up_status: &TaskList, test: &TestDecl) -> Option<Task> {
+    set_ups
+        .required_by(test)
+        .into_iter()
+        .find_map(|id| set_up_status.failure_cause(Task(id)))
+}
+
+/// Why a test did not complete normally
+enum TestFailure {
+    Error(Failed),
+    Panic(Option<String>),
+    Timeout(Duration),
+}
+
+fn run_test(
+    test_fn: TestFn,
+    ctx: TestContext,
+    timeout: Option<Duration>,
+    interrupt: &Interrupt,
+    handle: &Handle,
+) -> Result<(), TestFailure> {
+    // a blocking test can not be cancelled, an async one is dropped when
+    // the run is interrupted
+    let interrupted = || TestFailure::Error("interrupted".into());
+    match (test_fn, timeout) {
+        (TestFn::Sync(test_fn), None) => safe_run_sync_test(test_fn, ctx),
+        (TestFn::Sync(test_fn), Some(timeout)) => {
+            // a blocking test can not be cancelled, so it is left running on
+            // its own thread and the remaining tests carry on without it.
+            let (tx, rx) = std::sync::mpsc::channel();
+            thread::spawn(move || {
+                let _ = tx.send(safe_run_sync_test(test_fn, ctx));
+            });
+            rx.recv_timeout(timeout)
+                .unwrap_or(Err(TestFailure::Timeout(timeout)))
+        }
+        (TestFn::Async(test_fn), None) => handle
+            .block_on(interrupt.run_until(safe_run_test(test_fn, ctx)))
+            .unwrap_or_else(|| Err(interrupted())),
+        (TestFn::Async(test_fn), Some(timeout)) => handle.block_on(async {
+            interrupt
+                .run_until(tokio::time::timeout(timeout, safe_run_test(test_fn, ctx)))
+                .await
+                .unwrap_or_else(|| Ok(Err(interrupted())))
+                .unwrap_or(Err(TestFailure::Timeout(timeout)))
+        }),
+    }
+}
+
+/// Apply the `should_panic` expectation to the result of a test
+fn check_outcome(result: Result<(), TestFailure>, should_panic: ShouldPanic) -> Result<(), Failed> {
+    match (result, should_panic) {
+        (Ok(()), ShouldPanic::No) => Ok(()),
+        (Ok(()), _) => Err("test did not panic as expected".into()),
+        (Err(TestFailure::Panic(Some(msg))), ShouldPanic::No) => {
+            Err(format!("test panicked: {msg}").into())
+        }
+        (Err(TestFailure::Panic(None)), ShouldPanic::No) => Err("test panicked".into()),
+        (Err(TestFailure::Panic(_)), ShouldPanic::Yes) => Ok(()),
+        (Err(TestFailure::Panic(msg)), ShouldPanic::YesWithMessage(expected)) => {
+            match msg {
+                Some(msg) if msg.contains(expected) => Ok(()),
+                msg => Err(format!(
+                    "panic did not contain expected string\n      panic message: {:?}\n expected substring: {:?}",
+                    msg.unwrap_or_default(),
+                    expected
+                )
+                .into()),
+            }
+        }
+        (Err(TestFailure::Error(failed)), _) => Err(failed),
+        (Err(TestFailure::Timeout(timeout)), _) => {
+            Err(format!("test timed out after {:?}", timeout).into())
+        }
+    }
+}
+
+/// Render the error and its full `source()` chain.
+fn test_err(e: Box<dyn Error>) -> Failed {
+    let mut msg = format!("test returned error: {e}");
+    let mut source = e.source();
+    if source.is_some() {
+        msg.push_str("\n\nCaused by:");
+    }
+    let mut idx = 0;
+    while let Some(cause) = source {
+        write!(&mut msg, "\n    {idx}: {cause}").unwrap();
+        source = cause.source();
+        idx += 1;
+    }
+    msg.into()
+}
+
+fn safe_run_sync_test(
+    test_fn: fn(TestContext) -> TestResult,
+    ctx: TestContext,
+) -> Result<(), TestFailure> {
+    match panic::catch_unwind(AssertUnwindSafe(|| test_fn(ctx))) {
+        Ok(result) => result.map_err(|e| TestFailure::Error(test_err(e))),
+        Err(e) => Err(TestFailure::Panic(panic_message(e.as_ref()))),
+    }
+}
+
+async fn safe_run_test(
+    test_fn: fn(TestContext) -> TestFnOutput,
+    ctx: TestContext,
+) -> Result<(), TestFailure> {
+    let future = panic::catch_unwind(AssertUnwindSafe(|| test_fn(ctx)));
+
+    match future {
+        Ok(future) => AssertUnwindSafe(future)
+            .catch_unwind()
+            .await
+            .map_err(|e| TestFailure::Panic(panic_message(e.as_ref())))?
+            .map_err(|e| TestFailure::Error(test_err(e))),
+
+        Err(e) => Err(TestFailure::Panic(panic_message(e.as_ref()))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fmt;
+
+    #[derive(Debug)]
+    struct ChainErr(&'static str, Option<Box<ChainErr>>);
+
+    impl fmt::Display for ChainErr {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str(self.0)
+        }
+    }
+
+    impl Error for ChainErr {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            self.1.as_deref().map(|e| e as &(dyn Error + 'static))
+        }
+    }
+
+    #[test]
+    fn test_err_includes_source_chain() {
+        let err = ChainErr(
+            "request failed",
+            Some(Box::new(ChainErr(
+                "connection refused",
+                Some(Box::new(ChainErr("os error 111", None))),
+            ))),
+        );
+
+        assert_eq!(
+            Some("test returned error: request failed\n\nCaused by:\n    0: connection refused\n    1: os error 111"),
+            test_err(Box::new(err)).message()
+        );
+    }
+
+    #[test]
+    fn test_err_without_source() {
+        assert_eq!(
+            Some("test returned error: bad"),
+            test_err("bad".into()).message()
+        );
+    }
+
+    #[test]
+    fn should_panic_checks_the_panic_message() {
+        let panicked = || Err(TestFailure::Panic(Some("connection refused".to_owned())));
+
+        assert!(check_outcome(panicked(), ShouldPanic::Yes).is_ok());
+        assert!(check_outcome(panicked(), ShouldPanic::YesWithMessage("refused")).is_ok());
+        assert_eq!(
+            Some("panic did not contain expected string\n      panic message: \"connection refused\"\n expected substring: \"timeout\""),
+            check_outcome(panicked(), ShouldPanic::YesWithMessage("timeout"))
+                .unwrap_err()
+                .message()
+        );
+        assert_eq!(
+            Some("test did not panic as expected"),
+            check_outcome(Ok(()), ShouldPanic::Yes).unwrap_err().message()
+        );
+    }
+
+    #[test]
+    fn timeouts_are_reported() {
+        let timed_out = Err(TestFailure::Timeout(Duration::from_secs(30)));
+        assert_eq!(
+            Some("test timed out after 30s"),
+            check_outcome(timed_out, ShouldPanic::No).unwrap_err().message()
+        );
+    }
+}
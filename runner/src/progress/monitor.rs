@@ -36,6 +36,11 @@ enum ProgressEvent {
         duration: Option<Duration>,
         err_msg: Option<String>,
     },
+    TestNotRun {
+        name: String,
+        status: TaskStatus,
+        reason: String,
+    },
     FinalStatus {
         summary: OverallSummary,
     },
@@ -57,12 +62,10 @@ impl ProgressMonitor {
         });
 
         let listener = ProgressListener { tx };
-        let monitor = ProgressMonitor {
+        ProgressMonitor {
             handle,
             listener: listener.clone(),
-        };
-
-        monitor
+        }
     }
 
     pub fn listener(&self) -> ProgressListener {
@@ -71,7 +74,7 @@ impl ProgressMonitor {
 
     pub async fn shutdown(self) {
         self.listener.publish(ProgressEvent::Shutdown).await;
-        self.handle.await;
+        let _ = self.handle.await;
     }
 }
 
@@ -118,21 +121,62 @@ impl ProgressListener {
         .await
     }
 
-    pub async fn task_skipped(&self, phase: Phase, task: Task) {
+    /// The set up was not run, the component kept by an earlier run is used
+    pub async fn task_reused(&self, phase: Phase, task: Task) {
         self.publish(ProgressEvent::UpdateTask {
             phase,
             task,
-            status: TaskStatus::Skipped,
+            status: TaskStatus::Reused,
             duration: None,
             err_msg: None,
         })
         .await
     }
 
+    /// An attempt of a set up with retries failed and it will be run again
+    pub async fn task_retrying(&self, phase: Phase, task: Task, duration: Duration, err_msg: String) {
+        self.publish(ProgressEvent::UpdateTask {
+            phase,
+            task,
+            status: TaskStatus::Retrying,
+            duration: Some(duration),
+            err_msg: Some(err_msg),
+        })
+        .await
+    }
+
+    pub async fn task_skipped(&self, phase: Phase, task: Task, reason: String) {
+        self.publish(ProgressEvent::UpdateTask {
+            phase,
+            task,
+            status: TaskStatus::Skipped,
+            duration: None,
+            err_msg: Some(reason),
+        })
+        .await
+    }
+
+    pub async fn test_not_run(&self, name: String, status: TaskStatus, reason: String) {
+        self.publish(ProgressEvent::TestNotRun {
+            name,
+            status,
+            reason,
+        })
+        .await
+    }
+
     pub async fn finished(&self, summary: OverallSummary) {
         self.publish(ProgressEvent::FinalStatus { summary }).await
     }
 
+    /// Listener that drops every event, for tests
+    #[cfg(test)]
+    pub fn discard() -> ProgressListener {
+        let (tx, mut rx) = mpsc::channel(100);
+        tokio::spawn(async move { while rx.recv().await.is_some() {} });
+        ProgressListener { tx }
+    }
+
     async fn publish(&self, ev: ProgressEvent) {
         if let Some(err) = self.tx.send(ev).await.err() {
             println!("Failed to publish progress event {:?}", err.0);
@@ -187,6 +231,12 @@ impl MonitorWorker {
                 err_msg,
             } => self.log_update_task(task, status, duration, err_msg),
 
+            ProgressEvent::TestNotRun {
+                name,
+                status,
+                reason,
+            } => self.log_test_not_run(name, status, reason),
+
             ProgressEvent::FinalStatus { summary } => self.log_final_status(summary),
             ProgressEvent::Shutdown => panic!("Should not be logging shutdown event"),
         }
@@ -224,17 +274,40 @@ impl MonitorWorker {
         }
 
         if let Some(err_msg) = err_msg {
-            writeln!(
-                &mut self.stdout,
-                "\n\t{}{err_msg}{}",
-                self.styles.bad.render(),
-                self.styles.bad.render_reset()
-            )?;
+            writeln!(&mut self.stdout)?;
+            for line in err_msg.lines() {
+                writeln!(
+                    &mut self.stdout,
+                    "\t{}{line}{}",
+                    status_style.render(),
+                    status_style.render_reset()
+                )?;
+            }
         }
 
         Ok(())
     }
 
+    fn log_test_not_run(
+        &mut self,
+        name: String,
+        status: TaskStatus,
+        reason: String,
+    ) -> Result<(), io::Error> {
+        let status_style = self.styles.task_status(status);
+        let bold = self.styles.bold;
+
+        writeln!(
+            &mut self.stdout,
+            " {}{}{}  {}{status}{}: {reason}",
+            bold.render(),
+            name,
+            bold.render_reset(),
+            status_style.render(),
+            status_style.render_reset()
+        )
+    }
+
     fn log_phase_finished(&mut self, summary: PhaseSummary) -> Result<(), io::Error> {
         write!(&mut self.stdout, "\n{} ", summary.phase,)?;
 
@@ -252,7 +325,13 @@ impl MonitorWorker {
             result_style.render_reset()
         )?;
 
-        for status in &[TaskStatus::Ok, TaskStatus::Failed, TaskStatus::Skipped] {
+        for status in &[
+            TaskStatus::Ok,
+            TaskStatus::Reused,
+            TaskStatus::Failed,
+            TaskStatus::Skipped,
+            TaskStatus::Ignored,
+        ] {
             if let Some(count) = summary.counts.get(status) {
                 write!(&mut self.stdout, "{} {}; ", *count, status)?;
             }
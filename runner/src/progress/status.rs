@@ -4,18 +4,25 @@ use std::fmt;
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub enum TaskStatus {
     Running,
+    Retrying,
     Failed,
     Ok,
+    /// Left running by an earlier run with `--keep-env`
+    Reused,
     Skipped,
+    Ignored,
 }
 
 impl fmt::Display for TaskStatus {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let s = match self {
             TaskStatus::Running => "running",
+            TaskStatus::Retrying => "retrying",
             TaskStatus::Failed => "failed",
             TaskStatus::Ok => "ok",
+            TaskStatus::Reused => "reused",
             TaskStatus::Skipped => "skipped",
+            TaskStatus::Ignored => "ignored",
         };
         fmt::Display::fmt(s, f)
     }
@@ -12,9 +12,12 @@ impl Styles {
     pub fn task_status(&self, status: TaskStatus) -> Style {
         match status {
             TaskStatus::Running => self.norm,
+            TaskStatus::Retrying => self.bad,
             TaskStatus::Failed => self.bad,
             TaskStatus::Ok => self.good,
+            TaskStatus::Reused => self.good,
             TaskStatus::Skipped => self.norm,
+            TaskStatus::Ignored => self.norm,
         }
     }
 
@@ -72,8 +72,11 @@ impl PhaseSummaryBuilder {
 
     fn all_tasks_ok(&self) -> bool {
         let total: usize = self.counts.values().sum();
-        let okay = *self.counts.get(&TaskStatus::Ok).unwrap_or(&0);
-        okay == total
+        let okay = *self.counts.get(&TaskStatus::Ok).unwrap_or(&0)
+            + *self.counts.get(&TaskStatus::Reused).unwrap_or(&0);
+        // ignored tasks were deliberately not run, so do not fail the phase
+        let ignored = *self.counts.get(&TaskStatus::Ignored).unwrap_or(&0);
+        okay + ignored == total
     }
 
     fn result(&self) -> PhaseResult {
@@ -0,0 +1,183 @@
+use std::{
+    any::{Any, type_name},
+    error::Error,
+    fmt,
+    sync::Arc,
+};
+
+use dashmap::{DashMap, DashSet};
+
+use crate::TearDown;
+
+/// Output of a set up that hands a typed resource to its dependents.
+///
+/// Dependent set ups get the resource with `ctx.resource::<R>(name)`, tests
+/// by declaring an `#[resource(Name)] value: Arc<R>` argument.
+///
+/// Resources are not kept with `--keep-env`. A later run sets the environment
+/// up again when one of its set ups depends on a kept resource, but a test
+/// asking for one gets an error, it should use the component's params.
+pub struct Provides<R> {
+    resource: R,
+    tear_down: Option<Box<dyn TearDown>>,
+}
+
+impl<R: Any + Send + Sync> Provides<R> {
+    pub fn new(resource: R) -> Provides<R> {
+        Provides {
+            resource,
+            tear_down: None,
+        }
+    }
+
+    pub fn with_tear_down(self, tear_down: impl TearDown + 'static) -> Provides<R> {
+        Provides {
+            resource: self.resource,
+            tear_down: Some(Box::new(tear_down)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ResourceError {
+    // The component does not exist or did not return a resource
+    NotProvided {
+        component: String,
+    },
+
+    // The component provided a resource of a different type
+    TypeMismatch {
+        component: String,
+        requested: &'static str,
+        provided: &'static str,
+    },
+
+    // The component was kept by an earlier run, its set up did not run
+    NotKept {
+        component: String,
+    },
+}
+
+impl fmt::Display for ResourceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ResourceError::NotProvided { component } => {
+                write!(f, "#[set_up({})] did not provide a resource", component)
+            }
+            ResourceError::TypeMismatch {
+                component,
+                requested,
+                provided,
+            } => write!(
+                f,
+                "#[set_up({})] provides a `{}` but a `{}` was requested",
+                component, provided, requested
+            ),
+            ResourceError::NotKept { component } => write!(
+                f,
+                "#[set_up({})] was reused from a kept environment, resources are not kept, use its params instead",
+                component
+            ),
+        }
+    }
+}
+
+impl Error for ResourceError {}
+
+#[derive(Clone)]
+struct Resource {
+    type_name: &'static str,
+    value: Arc<dyn Any + Send + Sync>,
+}
+
+/// Resources provided by set ups, keyed by component name
+#[derive(Clone, Default)]
+pub(crate) struct Resources {
+    resources: Arc<DashMap<String, Resource>>,
+    kept: Arc<DashSet<String>>,
+}
+
+impl Resources {
+    /// Store the resource and hand back the tear down
+    pub fn provide<R: Any + Send + Sync>(
+        &self,
+        component: &str,
+        provides: Provides<R>,
+    ) -> Option<Box<dyn TearDown>> {
+        self.resources.insert(
+            component.to_owned(),
+            Resource {
+                type_name: type_name::<R>(),
+                value: Arc::new(provides.resource),
+            },
+        );
+        provides.tear_down
+    }
+
+    pub fn contains(&self, component: &str) -> bool {
+        self.resources.contains_key(component)
+    }
+
+    /// Forget the resource of a failed set up attempt
+    pub fn remove(&self, component: &str) {
+        self.resources.remove(component);
+    }
+
+    /// The component is reused from a kept environment, so it has no resource
+    pub fn mark_kept(&self, component: &str) {
+        self.kept.insert(component.to_owned());
+    }
+
+    pub fn get<R: Any + Send + Sync>(&self, component: &str) -> Result<Arc<R>, ResourceError> {
+        let resource = self
+            .resources
+            .get(component)
+            .map(|r| r.clone())
+            .ok_or_else(|| {
+                let component = component.to_owned();
+                if self.kept.contains(&component) {
+                    ResourceError::NotKept { component }
+                } else {
+                    ResourceError::NotProvided { component }
+                }
+            })?;
+
+        let provided = resource.type_name;
+        resource
+            .value
+            .downcast::<R>()
+            .map_err(|_| ResourceError::TypeMismatch {
+                component: component.to_owned(),
+                requested: type_name::<R>(),
+                provided,
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn can_get_provided_resource() {
+        let resources = Resources::default();
+        assert!(resources.provide("Postgres", Provides::new(42u32)).is_none());
+
+        assert_eq!(42, *resources.get::<u32>("Postgres").unwrap());
+    }
+
+    #[test]
+    fn reports_missing_and_mismatched_resources() {
+        let resources = Resources::default();
+        resources.provide("Postgres", Provides::new(42u32));
+
+        assert_eq!(
+            "#[set_up(Redis)] did not provide a resource",
+            resources.get::<u32>("Redis").unwrap_err().to_string()
+        );
+        assert_eq!(
+            "#[set_up(Postgres)] provides a `u32` but a `alloc::string::String` was requested",
+            resources.get::<String>("Postgres").unwrap_err().to_string()
+        );
+    }
+}
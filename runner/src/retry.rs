@@ -0,0 +1,59 @@
+use std::time::Duration;
+
+/// How often a failed set up is tried again, as declared with
+/// `#[set_up(Name, retries = 3, backoff = "exponential")]`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Retry {
+    pub retries: u32,
+    pub backoff: Backoff,
+}
+
+/// How long to wait before the next attempt
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Backoff {
+    /// The same delay before every attempt
+    Fixed(Duration),
+    /// The delay doubles after every failed attempt
+    Exponential(Duration),
+}
+
+impl Retry {
+    pub const NONE: Retry = Retry {
+        retries: 0,
+        backoff: Backoff::Fixed(Duration::ZERO),
+    };
+
+    /// Delay before the given retry, the first retry is 1
+    pub fn delay(&self, retry: u32) -> Duration {
+        match self.backoff {
+            Backoff::Fixed(delay) => delay,
+            Backoff::Exponential(base) => {
+                base.saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn delays_follow_the_backoff() {
+        let second = Duration::from_secs(1);
+        let fixed = Retry {
+            retries: 3,
+            backoff: Backoff::Fixed(second),
+        };
+        let exponential = Retry {
+            retries: 3,
+            backoff: Backoff::Exponential(second),
+        };
+
+        assert_eq!(vec![second; 3], (1..=3).map(|r| fixed.delay(r)).collect::<Vec<_>>());
+        assert_eq!(
+            vec![second, 2 * second, 4 * second],
+            (1..=3).map(|r| exponential.delay(r)).collect::<Vec<_>>()
+        );
+    }
+}
@@ -1,38 +1,79 @@
-use std::collections::{HashSet, VecDeque};
+use std::{
+    cmp::Reverse,
+    collections::{HashSet, VecDeque},
+};
 
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum Status {
     Waiting,
+    Running,
     Success,
     Skipped,
     Failed,
 }
 
+/// What happens to the remaining tasks when a task fails
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub enum FailureMode {
+    /// Skip every task that is still waiting, except those that only run
+    /// after the failed task and the tasks that depend on them
+    #[default]
+    SkipAll,
+    /// Only skip the tasks that (transitively) depend on the failed task
+    SkipDependents,
+}
+
+/// How a task depends on another
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Edge {
+    /// Waits for the other task, and is skipped if it fails
+    Requires,
+    /// Only waits for the other task to finish, whatever the outcome
+    After,
+}
+
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub struct Task(pub(crate) usize);
 
 #[derive(Clone)]
 struct TaskState {
     status: Status,
-    unblocks: Vec<Task>,
+    unblocks: Vec<(Task, Edge)>,
     blocked_by: HashSet<Task>,
+    skipped_because: Option<Task>,
+    // weight of the longest chain of dependents starting at this task
+    priority: u64,
 }
 
 #[derive(Clone)]
 pub struct TaskList {
     ready: VecDeque<Task>,
+    skipped: VecDeque<Task>,
     tasks: Vec<TaskState>,
+    failure_mode: FailureMode,
 }
 
 impl TaskList {
+    /// Task list where every task requires its deps
+    #[cfg(test)]
     pub fn new(deps: &[Vec<usize>]) -> Self {
+        let edges: Vec<Vec<(usize, Edge)>> = deps
+            .iter()
+            .map(|deps| deps.iter().map(|dep| (*dep, Edge::Requires)).collect())
+            .collect();
+        Self::from_edges(&edges)
+    }
+
+    pub fn from_edges(deps: &[Vec<(usize, Edge)>]) -> Self {
         let mut tasks = Vec::with_capacity(deps.len());
         let mut ready = VecDeque::new();
         for (task_id, deps) in deps.iter().enumerate() {
             tasks.push(TaskState {
                 status: Status::Waiting,
                 unblocks: Vec::new(),
-                blocked_by: deps.iter().map(|t| Task(*t)).collect(),
+                blocked_by: deps.iter().map(|(t, _)| Task(*t)).collect(),
+                skipped_because: None,
+                priority: 0,
             });
 
             if deps.is_empty() {
@@ -41,19 +82,61 @@ impl TaskList {
         }
 
         for (task_id, deps) in deps.iter().enumerate() {
-            for dep in deps {
-                tasks[*dep].unblocks.push(Task(task_id));
+            for (dep, edge) in deps {
+                tasks[*dep].unblocks.push((Task(task_id), *edge));
             }
         }
 
-        Self { ready, tasks }
+        Self {
+            ready,
+            skipped: VecDeque::new(),
+            tasks,
+            failure_mode: FailureMode::default(),
+        }
+    }
+
+    pub fn with_failure_mode(self, failure_mode: FailureMode) -> Self {
+        Self {
+            failure_mode,
+            ..self
+        }
+    }
+
+    /// Prefer ready tasks on the longest (weighted) path through their dependents.
+    ///
+    /// Weights are indexed by task, tasks without a weight are ready in FIFO order.
+    pub fn with_weights(mut self, weights: &[u64]) -> Self {
+        fn critical_path(id: usize, tasks: &mut [TaskState], weights: &[u64], done: &mut [bool]) -> u64 {
+            if !done[id] {
+                let dependents: Vec<Task> = tasks[id].unblocks.iter().map(|(t, _)| *t).collect();
+                let longest = dependents
+                    .into_iter()
+                    .map(|t| critical_path(t.0, tasks, weights, done))
+                    .max()
+                    .unwrap_or(0);
+                tasks[id].priority = weights[id] + longest;
+                done[id] = true;
+            }
+            tasks[id].priority
+        }
+
+        let mut done = vec![false; self.tasks.len()];
+        for id in 0..self.tasks.len() {
+            critical_path(id, &mut self.tasks, weights, &mut done);
+        }
+        self
+    }
+
+    /// Weight of the longest chain of dependents starting at the task
+    pub fn priority(&self, task: Task) -> u64 {
+        self.tasks[task.0].priority
     }
 
     pub fn set_status(&mut self, task: Task, next: Status) {
         let current = self.tasks[task.0].status;
         match (current, next) {
-            (Status::Waiting, Status::Success) => self.finish_task(task),
-            (Status::Waiting, Status::Failed) => self.fail_task(task),
+            (Status::Waiting | Status::Running, Status::Success) => self.finish_task(task),
+            (Status::Waiting | Status::Running, Status::Failed) => self.fail_task(task),
             _ => panic!(
                 "Invalid status change for {:?} ({:?} -> {:?})",
                 task, current, next
@@ -62,41 +145,132 @@ impl TaskList {
     }
 
     fn finish_task(&mut self, finished: Task) {
-        while let Some(unblocked_id) = self.tasks[finished.0].unblocks.pop() {
+        self.unblock(finished, |_| true);
+        self.tasks[finished.0].status = Status::Success;
+    }
+
+    /// Release the dependents (selected by edge) waiting on a finished task
+    fn unblock(&mut self, finished: Task, follow: impl Fn(Edge) -> bool) {
+        let unblocks = std::mem::take(&mut self.tasks[finished.0].unblocks);
+        for (unblocked_id, edge) in unblocks {
+            if !follow(edge) {
+                continue;
+            }
             let blocked = &mut self.tasks[unblocked_id.0];
             assert!(blocked.blocked_by.remove(&finished));
-            if blocked.blocked_by.is_empty() {
+            if blocked.blocked_by.is_empty() && blocked.status == Status::Waiting {
                 self.ready.push_back(unblocked_id);
             }
         }
-        self.tasks[finished.0].status = Status::Success;
     }
 
     fn fail_task(&mut self, failed: Task) {
         self.tasks[failed.0].status = Status::Failed;
-        self.ready.clear();
-        for task in &mut self.tasks {
-            if task.status == Status::Waiting {
-                task.status = Status::Skipped;
+        let mut finished = vec![failed];
+
+        if self.failure_mode == FailureMode::SkipAll {
+            // only the tasks that run after the failed one, and what depends
+            // on them, are left to go ahead
+            let mut after_failed = HashSet::new();
+            let mut pending: Vec<Task> = self.tasks[failed.0]
+                .unblocks
+                .iter()
+                .filter(|(_, edge)| *edge == Edge::After)
+                .map(|(task, _)| *task)
+                .collect();
+            while let Some(task) = pending.pop() {
+                if after_failed.insert(task) {
+                    pending.extend(self.tasks[task.0].unblocks.iter().map(|(t, _)| *t));
+                }
+            }
+            self.ready.clear();
+            for idx in 0..self.tasks.len() {
+                let task = Task(idx);
+                if self.tasks[idx].status == Status::Waiting && !after_failed.contains(&task) {
+                    self.skip(task, failed);
+                    finished.push(task);
+                }
             }
         }
+
+        // skip the tasks that require a failed or skipped task
+        let mut pending = finished.clone();
+        while let Some(skipped) = pending.pop() {
+            for (blocked, edge) in self.tasks[skipped.0].unblocks.clone() {
+                if edge == Edge::Requires && self.tasks[blocked.0].status == Status::Waiting {
+                    self.skip(blocked, failed);
+                    finished.push(blocked);
+                    pending.push(blocked);
+                }
+            }
+        }
+
+        // tasks that only run after the failed (or skipped) ones can go ahead
+        for task in finished {
+            self.unblock(task, |edge| edge == Edge::After);
+        }
+    }
+
+    fn skip(&mut self, task: Task, failed: Task) {
+        let state = &mut self.tasks[task.0];
+        state.status = Status::Skipped;
+        state.skipped_because = Some(failed);
+        self.skipped.push_back(task);
     }
 
     pub fn pop_ready(&mut self) -> Option<Vec<Task>> {
         // could be simpler.
         let mut ready = Vec::new();
         while let Some(idx) = self.ready.pop_front() {
+            self.tasks[idx.0].status = Status::Running;
             ready.push(idx)
         }
+        ready.sort_by_key(|t| Reverse(self.tasks[t.0].priority));
         if ready.is_empty() { None } else { Some(ready) }
     }
 
+    /// Tasks that have been skipped since the last call
+    pub fn pop_skipped(&mut self) -> Option<Vec<Task>> {
+        let skipped: Vec<Task> = self.skipped.drain(..).collect();
+        if skipped.is_empty() { None } else { Some(skipped) }
+    }
+
+    /// The failed task responsible for this task not succeeding (if any)
+    pub fn failure_cause(&self, task: Task) -> Option<Task> {
+        let state = &self.tasks[task.0];
+        match state.status {
+            Status::Failed => Some(task),
+            Status::Skipped => state.skipped_because,
+            _ => None,
+        }
+    }
+
+    /// Skip every task that has not finished, for when the run is interrupted
+    pub fn cancel_remaining(&mut self) -> Vec<Task> {
+        self.ready.clear();
+        let mut cancelled = Vec::new();
+        for (idx, task) in self.tasks.iter_mut().enumerate() {
+            if task.status == Status::Waiting || task.status == Status::Running {
+                task.status = Status::Skipped;
+                cancelled.push(Task(idx));
+            }
+        }
+        cancelled
+    }
+
+    pub fn succeeded(&self, task: Task) -> bool {
+        self.tasks[task.0].status == Status::Success
+    }
+
     pub fn all_success(&self) -> bool {
         self.tasks.iter().all(|t| t.status == Status::Success)
     }
 
     pub fn none_waiting(&self) -> bool {
-        !self.tasks.iter().any(|t| t.status == Status::Waiting)
+        !self
+            .tasks
+            .iter()
+            .any(|t| t.status == Status::Waiting || t.status == Status::Running)
     }
 }
 
@@ -122,6 +296,17 @@ mod test {
         assert_eq!(None, tasks.pop_ready());
     }
 
+    #[test]
+    fn cancelling_skips_unfinished_tasks() {
+        let mut tasks = TaskList::new(&[vec![], vec![0], vec![]]);
+        assert_eq!(Some(vec![Task(0), Task(2)]), tasks.pop_ready());
+        tasks.set_status(Task(2), Status::Success);
+
+        assert_eq!(vec![Task(0), Task(1)], tasks.cancel_remaining());
+        assert!(tasks.none_waiting());
+        assert_eq!(None, tasks.pop_ready());
+    }
+
     #[test]
     fn check_all_finished() {
         let mut tasks = TaskList::new(&[vec![], vec![0], vec![]]);
@@ -132,4 +317,88 @@ mod test {
         tasks.set_status(Task(2), Status::Success);
         assert_eq!(true, tasks.all_success());
     }
+
+    #[test]
+    fn failure_skips_all_waiting_tasks_by_default() {
+        let mut tasks = TaskList::new(&[vec![], vec![0], vec![], vec![2]]);
+        assert_eq!(Some(vec![Task(0), Task(2)]), tasks.pop_ready());
+
+        tasks.set_status(Task(0), Status::Failed);
+        assert_eq!(Some(vec![Task(1), Task(3)]), tasks.pop_skipped());
+        assert_eq!(Some(Task(0)), tasks.failure_cause(Task(3)));
+
+        // tasks that were already running can still finish
+        tasks.set_status(Task(2), Status::Success);
+        assert!(tasks.none_waiting());
+    }
+
+    #[test]
+    fn failure_only_skips_dependents_when_requested() {
+        let mut tasks = TaskList::new(&[vec![], vec![0], vec![1], vec![]])
+            .with_failure_mode(FailureMode::SkipDependents);
+        assert_eq!(Some(vec![Task(0), Task(3)]), tasks.pop_ready());
+
+        tasks.set_status(Task(0), Status::Failed);
+        assert_eq!(Some(vec![Task(1), Task(2)]), tasks.pop_skipped());
+        assert_eq!(None, tasks.pop_ready());
+        assert!(!tasks.none_waiting());
+
+        tasks.set_status(Task(3), Status::Success);
+        assert!(tasks.none_waiting());
+        assert_eq!(None, tasks.failure_cause(Task(3)));
+        assert_eq!(Some(Task(0)), tasks.failure_cause(Task(2)));
+    }
+
+    #[test]
+    fn ready_tasks_on_the_critical_path_come_first() {
+        // 0 and 2 are leaves, the slow 3 waits on 2
+        let mut tasks = TaskList::new(&[vec![], vec![0], vec![], vec![2]]).with_weights(&[1, 1, 1, 10]);
+        assert_eq!(2, tasks.priority(Task(0)));
+        assert_eq!(11, tasks.priority(Task(2)));
+        assert_eq!(Some(vec![Task(2), Task(0)]), tasks.pop_ready());
+    }
+
+    #[test]
+    fn failure_does_not_skip_tasks_that_only_run_after() {
+        let mut tasks = TaskList::from_edges(&[
+            vec![],
+            vec![(0, Edge::Requires)],
+            vec![(1, Edge::After)],
+            vec![(0, Edge::After)],
+        ])
+        .with_failure_mode(FailureMode::SkipDependents);
+        assert_eq!(Some(vec![Task(0)]), tasks.pop_ready());
+
+        tasks.set_status(Task(0), Status::Failed);
+        assert_eq!(Some(vec![Task(1)]), tasks.pop_skipped());
+        assert_eq!(Some(vec![Task(3), Task(2)]), tasks.pop_ready());
+        assert_eq!(None, tasks.failure_cause(Task(2)));
+    }
+
+    #[test]
+    fn skip_all_still_runs_tasks_that_only_run_after() {
+        let mut tasks = TaskList::from_edges(&[
+            vec![],
+            vec![(0, Edge::After)],
+            vec![(0, Edge::After), (3, Edge::Requires)],
+            vec![],
+            vec![(1, Edge::Requires)],
+            vec![(1, Edge::Requires), (6, Edge::Requires)],
+            vec![(3, Edge::Requires)],
+        ]);
+        assert_eq!(Some(vec![Task(0), Task(3)]), tasks.pop_ready());
+
+        // 5 depends on 1, but also on 6 which is skipped
+        tasks.set_status(Task(0), Status::Failed);
+        assert_eq!(Some(vec![Task(6), Task(5)]), tasks.pop_skipped());
+        assert_eq!(Some(vec![Task(1)]), tasks.pop_ready());
+
+        tasks.set_status(Task(1), Status::Success);
+        assert_eq!(Some(vec![Task(4)]), tasks.pop_ready());
+
+        tasks.set_status(Task(3), Status::Success);
+        assert_eq!(Some(vec![Task(2)]), tasks.pop_ready());
+        assert_eq!(Some(Task(0)), tasks.failure_cause(Task(5)));
+        assert_eq!(None, tasks.pop_skipped());
+    }
 }